@@ -0,0 +1,91 @@
+//! An in-process Esplora HTTP API with scripted UTXOs and fee estimates that records every
+//! broadcast transaction, so tests can inspect what a Bitcoin client actually sent.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use serde_json::{json, Value};
+
+use crate::http::{serve_http, Request, Response};
+
+#[derive(Default)]
+struct State {
+    utxos: HashMap<String, Value>,
+    fee_estimates: Value,
+    txid: Option<String>,
+    broadcasts: Vec<String>,
+}
+
+pub struct FakeEsplora {
+    url: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeEsplora {
+    /// Starts the server on a random local port. Addresses without UTXOs have none, and
+    /// broadcasts are rejected until a txid is set with [`Self::set_txid`].
+    pub async fn start() -> Self {
+        let state = Arc::new(Mutex::new(State {
+            fee_estimates: json!({}),
+            ..Default::default()
+        }));
+        let server_state = state.clone();
+        let url = serve_http(move |request| handle_request(&server_state, request)).await;
+
+        Self { url, state }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Answers `GET /address/:address/utxo` with `utxos`.
+    pub fn set_utxos(&self, address: &str, utxos: Value) {
+        self.state
+            .lock()
+            .unwrap()
+            .utxos
+            .insert(address.to_string(), utxos);
+    }
+
+    /// Answers `GET /fee-estimates` with `fee_estimates`, a map from confirmation targets to
+    /// fee rates in sat/vB.
+    pub fn set_fee_estimates(&self, fee_estimates: Value) {
+        self.state.lock().unwrap().fee_estimates = fee_estimates;
+    }
+
+    /// Accepts every `POST /tx` and answers it with `txid`.
+    pub fn set_txid(&self, txid: &str) {
+        self.state.lock().unwrap().txid = Some(txid.to_string());
+    }
+
+    /// The hex-encoded transactions posted to `/tx` so far, in order.
+    pub fn broadcasts(&self) -> Vec<String> {
+        self.state.lock().unwrap().broadcasts.clone()
+    }
+}
+
+fn handle_request(state: &Mutex<State>, request: &Request) -> Response {
+    let mut state = state.lock().unwrap();
+
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/fee-estimates") => Response::json(&state.fee_estimates),
+        ("GET", path) => match path
+            .strip_prefix("/address/")
+            .and_then(|path| path.strip_suffix("/utxo"))
+        {
+            Some(address) => Response::json(state.utxos.get(address).unwrap_or(&json!([]))),
+            None => Response::text(404, "not found"),
+        },
+        ("POST", "/tx") => {
+            state
+                .broadcasts
+                .push(String::from_utf8_lossy(&request.body).into_owned());
+            match &state.txid {
+                Some(txid) => Response::text(200, txid.clone()),
+                None => Response::text(400, "sendrawtransaction RPC error: rejected"),
+            }
+        }
+        _ => Response::text(404, "not found"),
+    }
+}
@@ -0,0 +1,123 @@
+//! An in-process Ethereum JSON-RPC server with scripted responses that records every request,
+//! so tests can inspect what a client actually sent (for example the signed raw transaction).
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use serde_json::{json, Value};
+
+use crate::http::serve;
+
+#[derive(Default)]
+struct State {
+    queued: HashMap<String, VecDeque<Result<Value, Value>>>,
+    fixed: HashMap<String, Result<Value, Value>>,
+    requests: Vec<(String, Value)>,
+}
+
+pub struct FakeEvmRpc {
+    url: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeEvmRpc {
+    /// Starts the server on a random local port. Methods without a response answer with a
+    /// "method not found" error.
+    pub async fn start() -> Self {
+        let state = Arc::new(Mutex::new(State::default()));
+        let server_state = state.clone();
+        let url = serve(move |body| handle_request(&server_state, body)).await;
+
+        Self { url, state }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Answers every `method` call with `result`, once the queued responses are used up.
+    pub fn set_response(&self, method: &str, result: Value) {
+        self.state
+            .lock()
+            .unwrap()
+            .fixed
+            .insert(method.to_string(), Ok(result));
+    }
+
+    /// Answers every `method` call with a JSON-RPC error, once the queued responses are used up.
+    pub fn set_error(&self, method: &str, code: i64, message: &str, data: Option<Value>) {
+        self.state
+            .lock()
+            .unwrap()
+            .fixed
+            .insert(method.to_string(), Err(rpc_error(code, message, data)));
+    }
+
+    /// Answers the next `method` call with `result`. Queued responses are used in order.
+    pub fn push_response(&self, method: &str, result: Value) {
+        self.queue(method, Ok(result));
+    }
+
+    /// Answers the next `method` call with a JSON-RPC error.
+    pub fn push_error(&self, method: &str, code: i64, message: &str, data: Option<Value>) {
+        self.queue(method, Err(rpc_error(code, message, data)));
+    }
+
+    /// The params of every `method` call received so far, in order.
+    pub fn requests(&self, method: &str) -> Vec<Value> {
+        self.state
+            .lock()
+            .unwrap()
+            .requests
+            .iter()
+            .filter(|(name, _)| name == method)
+            .map(|(_, params)| params.clone())
+            .collect()
+    }
+
+    fn queue(&self, method: &str, response: Result<Value, Value>) {
+        self.state
+            .lock()
+            .unwrap()
+            .queued
+            .entry(method.to_string())
+            .or_default()
+            .push_back(response);
+    }
+}
+
+fn rpc_error(code: i64, message: &str, data: Option<Value>) -> Value {
+    match data {
+        Some(data) => json!({ "code": code, "message": message, "data": data }),
+        None => json!({ "code": code, "message": message }),
+    }
+}
+
+fn handle_request(state: &Mutex<State>, body: &[u8]) -> Value {
+    let request: Value = serde_json::from_slice(body).unwrap_or_default();
+    let id = request["id"].clone();
+    let method = request["method"].as_str().unwrap_or_default().to_string();
+    let mut state = state.lock().unwrap();
+
+    state
+        .requests
+        .push((method.clone(), request["params"].clone()));
+
+    let response = state
+        .queued
+        .get_mut(&method)
+        .and_then(VecDeque::pop_front)
+        .or_else(|| state.fixed.get(&method).cloned())
+        .unwrap_or_else(|| {
+            Err(rpc_error(
+                -32601,
+                &format!("the method {} does not exist/is not available", method),
+                None,
+            ))
+        });
+
+    match response {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+    }
+}
@@ -0,0 +1,115 @@
+//! Minimal HTTP/1.1 transport shared by the fake servers.
+
+use std::sync::Arc;
+
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// A request received by [`serve_http`].
+pub(crate) struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+/// A response sent by [`serve_http`].
+pub(crate) struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn json(body: &Value) -> Self {
+        Self {
+            status: 200,
+            content_type: "application/json",
+            body: body.to_string(),
+        }
+    }
+
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: "text/plain",
+            body: body.into(),
+        }
+    }
+}
+
+type Handler = Arc<dyn Fn(&Request) -> Response + Send + Sync>;
+
+/// Serves `handler` on a random local port and returns the server URL. Every request body is
+/// passed to `handler` and its return value is sent back as the JSON response body.
+pub(crate) async fn serve(handler: impl Fn(&[u8]) -> Value + Send + Sync + 'static) -> String {
+    serve_http(move |request| Response::json(&handler(&request.body))).await
+}
+
+/// Like [`serve`], but `handler` sees the method and path of every request and picks the status
+/// and content type of its response.
+pub(crate) async fn serve_http(
+    handler: impl Fn(&Request) -> Response + Send + Sync + 'static,
+) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("failed to bind fake RPC server");
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let handler: Handler = Arc::new(handler);
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(serve_connection(stream, handler.clone()));
+        }
+    });
+
+    url
+}
+
+async fn serve_connection(stream: TcpStream, handler: Handler) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(());
+        }
+        let mut request_line = line.split_whitespace();
+        let method = request_line.next().unwrap_or_default().to_string();
+        let path = request_line.next().unwrap_or_default().to_string();
+
+        let mut content_length = 0;
+        loop {
+            line.clear();
+            reader.read_line(&mut line).await?;
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or_default();
+                }
+            }
+        }
+
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).await?;
+
+        let response = handler(&Request { method, path, body });
+        writer
+            .write_all(
+                format!(
+                    "HTTP/1.1 {} {}\r\ncontent-type: {}\r\ncontent-length: {}\r\n\r\n{}",
+                    response.status,
+                    if response.status < 400 { "OK" } else { "Error" },
+                    response.content_type,
+                    response.body.len(),
+                    response.body
+                )
+                .as_bytes(),
+            )
+            .await?;
+    }
+}
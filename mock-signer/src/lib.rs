@@ -0,0 +1,178 @@
+use k256::ecdsa::{SigningKey, VerifyingKey};
+use near_sdk::{env, near, AccountId, CurveType, PanicOnDefault, PublicKey};
+use utils::{
+    error::KdfError,
+    kdf::{derive_epsilon_for_version, verifying_key_to_naj_pk},
+    types::{DerivationPath, KeyVersion, SignRequest, SignatureResponse},
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod esplora;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod evm;
+#[cfg(not(target_arch = "wasm32"))]
+mod http;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod server;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod signing_service;
+
+/// Root secret used by [`MockSigner::default`]. Never use it outside of tests.
+pub const DEFAULT_ROOT_SECRET_KEY: [u8; 32] = [
+    0x4c, 0x0e, 0x1a, 0x6f, 0x8b, 0x3d, 0x52, 0x97, 0x2e, 0x6a, 0x11, 0xc4, 0x7f, 0x05, 0x93, 0xd8,
+    0x21, 0x6b, 0xee, 0x40, 0x1c, 0x7a, 0x35, 0x88, 0x9f, 0x02, 0x64, 0xbd, 0x13, 0x57, 0xa9, 0x30,
+];
+
+/// Signs like the MPC network would, but with a single known root secret key, so that
+/// signatures can be produced and checked without a running MPC network.
+#[derive(Clone)]
+pub struct MockSigner {
+    root_signing_key: SigningKey,
+}
+
+impl Default for MockSigner {
+    fn default() -> Self {
+        Self::from_bytes(&DEFAULT_ROOT_SECRET_KEY).expect("default root secret key is valid")
+    }
+}
+
+impl MockSigner {
+    pub fn new(root_signing_key: SigningKey) -> Self {
+        Self { root_signing_key }
+    }
+
+    pub fn from_bytes(secret_key: &[u8]) -> Result<Self, k256::ecdsa::Error> {
+        Ok(Self::new(SigningKey::from_slice(secret_key)?))
+    }
+
+    pub fn root_public_key(&self) -> VerifyingKey {
+        *self.root_signing_key.verifying_key()
+    }
+
+    /// The root key in the `secp256k1:<base58>` form returned by the signer contract.
+    pub fn naj_public_key(&self) -> String {
+        verifying_key_to_naj_pk(&self.root_public_key())
+    }
+
+    /// Derives the child signing key whose public key `derive_child_public_key` returns.
+    pub fn derive_signing_key(
+        &self,
+        predecessor: &AccountId,
+        path: &DerivationPath,
+        key_version: KeyVersion,
+    ) -> Result<SigningKey, KdfError> {
+        let epsilon =
+            derive_epsilon_for_version(key_version, predecessor.to_string(), path.clone())?;
+        let child_scalar = *self.root_signing_key.as_nonzero_scalar().as_ref() + epsilon;
+
+        Ok(SigningKey::from_bytes(&child_scalar.to_bytes())?)
+    }
+
+    /// Signs `request.payload` with the key derived for `predecessor` and `request.path`.
+    pub fn sign(
+        &self,
+        predecessor: &AccountId,
+        request: &SignRequest,
+    ) -> Result<SignatureResponse, KdfError> {
+        let signing_key =
+            self.derive_signing_key(predecessor, &request.path, KeyVersion(request.key_version))?;
+        let (signature, recovery_id) = signing_key.sign_prehash_recoverable(&request.payload)?;
+
+        Ok(SignatureResponse::from_recoverable(&signature, recovery_id)
+            .expect("freshly produced signatures have a valid big_r"))
+    }
+}
+
+/// A stand-in for the MPC signer contract exposing the same `sign`/`public_key` interface
+/// as `ext_signature_contract`, backed by a [`MockSigner`].
+#[near(contract_state)]
+#[derive(PanicOnDefault)]
+pub struct MockSignatureContract {
+    root_secret_key: Vec<u8>,
+}
+
+#[near]
+impl MockSignatureContract {
+    #[init]
+    pub fn new(root_secret_key: Option<Vec<u8>>) -> Self {
+        let root_secret_key = root_secret_key.unwrap_or(DEFAULT_ROOT_SECRET_KEY.to_vec());
+        if MockSigner::from_bytes(&root_secret_key).is_err() {
+            env::panic_str("Invalid root secret key");
+        }
+
+        Self { root_secret_key }
+    }
+
+    #[payable]
+    pub fn sign(&mut self, request: SignRequest) -> SignatureResponse {
+        self.signer()
+            .sign(&env::predecessor_account_id(), &request)
+            .unwrap_or_else(|err| env::panic_str(&err.to_string()))
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        let encoded_point = self.signer().root_public_key().to_encoded_point(false);
+        PublicKey::from_parts(CurveType::SECP256K1, encoded_point.as_bytes()[1..].to_vec())
+            .unwrap_or_else(|_| env::panic_str("Invalid root public key"))
+    }
+}
+
+impl MockSignatureContract {
+    fn signer(&self) -> MockSigner {
+        MockSigner::from_bytes(&self.root_secret_key)
+            .unwrap_or_else(|_| env::panic_str("Invalid root secret key"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k256::ecdsa::{signature::hazmat::PrehashVerifier, RecoveryId, Signature};
+    use k256::elliptic_curve::point::AffineCoordinates;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::testing_env;
+    use utils::kdf::{derive_child_public_key, near_sdk_pk_to_verifying_key};
+
+    #[test]
+    fn test_sign_matches_derived_public_key() {
+        let predecessor: AccountId = "alice.test.near".parse().unwrap();
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(predecessor.clone())
+            .build());
+
+        let mut contract = MockSignatureContract::new(None);
+        let root_public_key = near_sdk_pk_to_verifying_key(&contract.public_key()).unwrap();
+        assert_eq!(root_public_key, MockSigner::default().root_public_key());
+
+        let request = SignRequest {
+            payload: [7; 32],
+            path: "ethereum,1".parse().unwrap(),
+            key_version: 0,
+        };
+        let response = contract.sign(request);
+
+        let child_public_key = derive_child_public_key(
+            &root_public_key,
+            predecessor.to_string(),
+            "ethereum,1".parse().unwrap(),
+        )
+        .unwrap();
+
+        let signature = Signature::from_scalars(
+            response.big_r.affine_point.x(),
+            response.s.scalar.to_bytes(),
+        )
+        .unwrap();
+        child_public_key
+            .verify_prehash(&[7; 32], &signature)
+            .unwrap();
+
+        let recovered = VerifyingKey::recover_from_prehash(
+            &[7; 32],
+            &signature,
+            RecoveryId::from_byte(response.recovery_id).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(recovered, child_public_key);
+    }
+}
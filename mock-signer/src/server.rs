@@ -0,0 +1,526 @@
+//! An in-process NEAR JSON-RPC server that executes `sign` calls against a [`MockSigner`],
+//! so the `rpc` clients can be exercised end to end without testnet access.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use near_crypto::{KeyType, PublicKey, Signature};
+use near_jsonrpc_client::JsonRpcClient;
+use near_jsonrpc_primitives::errors::ServerError;
+use near_jsonrpc_primitives::types::{
+    query::{QueryResponseKind, RpcQueryRequest, RpcQueryResponse},
+    transactions::{RpcTransactionError, RpcTransactionResponse},
+};
+use near_primitives::{
+    borsh::BorshDeserialize,
+    errors::{
+        ActionError, ActionErrorKind, FunctionCallError, InvalidTxError, MethodResolveError,
+        TxExecutionError,
+    },
+    hash::CryptoHash,
+    transaction::{Action, FunctionCallAction, SignedTransaction},
+    types::AccountId,
+    views::{
+        AccessKeyPermissionView, AccessKeyView, BlockHeaderView, BlockView, CallResult,
+        ExecutionMetadataView, ExecutionOutcomeView, ExecutionOutcomeWithIdView,
+        ExecutionStatusView, FinalExecutionOutcomeView, FinalExecutionOutcomeViewEnum,
+        FinalExecutionStatus, QueryRequest, SignedTransactionView, TxExecutionStatus,
+    },
+};
+use serde_json::{json, Value};
+use utils::types::SignRequest;
+
+use crate::{http::serve, MockSigner};
+
+const BLOCK_HEIGHT: u64 = 100;
+
+struct State {
+    signer: MockSigner,
+    contract_id: AccountId,
+    access_key_nonces: HashMap<(AccountId, PublicKey), u64>,
+    transactions: HashMap<CryptoHash, Result<FinalExecutionOutcomeView, InvalidTxError>>,
+    execution_progress: Vec<TxExecutionStatus>,
+    polls: HashMap<CryptoHash, usize>,
+    send_tx_timeout: bool,
+    signature_deposit: Option<u128>,
+    function_calls: Vec<FunctionCallAction>,
+}
+
+pub struct FakeNearRpc {
+    url: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeNearRpc {
+    /// Starts the server on a random local port, serving `contract_id` as the signer contract.
+    pub async fn start(signer: MockSigner, contract_id: AccountId) -> Self {
+        let state = Arc::new(Mutex::new(State {
+            signer,
+            contract_id,
+            access_key_nonces: HashMap::new(),
+            transactions: HashMap::new(),
+            execution_progress: vec![],
+            polls: HashMap::new(),
+            send_tx_timeout: false,
+            signature_deposit: None,
+            function_calls: vec![],
+        }));
+
+        let server_state = state.clone();
+        let url = serve(move |body| handle_request(&server_state, body)).await;
+
+        Self { url, state }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn client(&self) -> JsonRpcClient {
+        JsonRpcClient::connect(&self.url)
+    }
+
+    pub fn set_access_key_nonce(&self, account_id: AccountId, public_key: PublicKey, nonce: u64) {
+        self.state
+            .lock()
+            .unwrap()
+            .access_key_nonces
+            .insert((account_id, public_key), nonce);
+    }
+
+    pub fn access_key_nonce(&self, account_id: &AccountId, public_key: &PublicKey) -> u64 {
+        let state = self.state.lock().unwrap();
+        state
+            .access_key_nonces
+            .get(&(account_id.clone(), public_key.clone()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Reports `statuses` to the first status polls of every transaction, one per poll, before
+    /// reporting it as final. Executed statuses come with the execution outcome, like on a node.
+    pub fn set_execution_progress(&self, statuses: Vec<TxExecutionStatus>) {
+        self.state.lock().unwrap().execution_progress = statuses;
+    }
+
+    /// Makes `send_tx` answer with a timeout error, like a node that stopped waiting for the
+    /// outcome. The transaction is still executed.
+    pub fn set_send_tx_timeout(&self, send_tx_timeout: bool) {
+        self.state.lock().unwrap().send_tx_timeout = send_tx_timeout;
+    }
+
+    /// Makes the contract charge `deposit` per signature and report it through the
+    /// `experimental_signature_deposit` view, like the production signer contract. Without it,
+    /// the contract has no such view and accepts any deposit.
+    pub fn set_signature_deposit(&self, deposit: Option<u128>) {
+        self.state.lock().unwrap().signature_deposit = deposit;
+    }
+
+    /// Every function call executed so far, in order.
+    pub fn function_calls(&self) -> Vec<FunctionCallAction> {
+        self.state.lock().unwrap().function_calls.clone()
+    }
+
+    /// Whether a transaction with `tx_hash` was received, including rejected ones.
+    pub fn has_transaction(&self, tx_hash: &CryptoHash) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .transactions
+            .contains_key(tx_hash)
+    }
+
+    /// Number of transactions received, including rejected ones.
+    pub fn transaction_count(&self) -> usize {
+        self.state.lock().unwrap().transactions.len()
+    }
+}
+
+fn handle_request(state: &Mutex<State>, body: &[u8]) -> Value {
+    let request: Value = serde_json::from_slice(body).unwrap_or_default();
+    let id = request["id"].clone();
+    let params = request["params"].clone();
+    let mut state = state.lock().unwrap();
+
+    let result = match request["method"].as_str().unwrap_or_default() {
+        "block" => Ok(block()),
+        "query" => state.query(params),
+        "broadcast_tx_async" => state.broadcast_tx_async(params),
+        "send_tx" => state.send_tx(params),
+        "tx" => state.tx(params),
+        method => Err(json!({
+            "name": "REQUEST_VALIDATION_ERROR",
+            "cause": { "name": "METHOD_NOT_FOUND", "info": { "method_name": method } },
+            "code": -32601,
+            "message": "Method not found",
+        })),
+    };
+
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+    }
+}
+
+fn handler_error(cause: impl near_sdk::serde::Serialize) -> Value {
+    json!({
+        "name": "HANDLER_ERROR",
+        "cause": cause,
+        "code": -32000,
+        "message": "Server error",
+    })
+}
+
+fn block_hash() -> CryptoHash {
+    CryptoHash::hash_bytes(&BLOCK_HEIGHT.to_le_bytes())
+}
+
+fn block() -> Value {
+    let header = BlockHeaderView {
+        height: BLOCK_HEIGHT,
+        prev_height: Some(BLOCK_HEIGHT - 1),
+        epoch_id: CryptoHash::default(),
+        next_epoch_id: CryptoHash::default(),
+        hash: block_hash(),
+        prev_hash: CryptoHash::default(),
+        prev_state_root: CryptoHash::default(),
+        block_body_hash: None,
+        chunk_receipts_root: CryptoHash::default(),
+        chunk_headers_root: CryptoHash::default(),
+        chunk_tx_root: CryptoHash::default(),
+        outcome_root: CryptoHash::default(),
+        chunks_included: 0,
+        challenges_root: CryptoHash::default(),
+        timestamp: 0,
+        timestamp_nanosec: 0,
+        random_value: CryptoHash::default(),
+        validator_proposals: vec![],
+        chunk_mask: vec![],
+        gas_price: 100_000_000,
+        block_ordinal: Some(BLOCK_HEIGHT),
+        rent_paid: 0,
+        validator_reward: 0,
+        total_supply: 0,
+        challenges_result: vec![],
+        last_final_block: CryptoHash::default(),
+        last_ds_final_block: CryptoHash::default(),
+        next_bp_hash: CryptoHash::default(),
+        block_merkle_root: CryptoHash::default(),
+        epoch_sync_data_hash: None,
+        approvals: vec![],
+        signature: Signature::empty(KeyType::ED25519),
+        latest_protocol_version: 0,
+    };
+
+    serde_json::to_value(BlockView {
+        author: "validator.test.near".parse().unwrap(),
+        header,
+        chunks: vec![],
+    })
+    .unwrap()
+}
+
+impl State {
+    fn query(&self, params: Value) -> Result<Value, Value> {
+        let request: RpcQueryRequest =
+            serde_json::from_value(params).map_err(|e| handler_error(e.to_string()))?;
+
+        let kind = match request.request {
+            QueryRequest::ViewAccessKey {
+                account_id,
+                public_key,
+            } => QueryResponseKind::AccessKey(AccessKeyView {
+                nonce: self
+                    .access_key_nonces
+                    .get(&(account_id, public_key))
+                    .copied()
+                    .unwrap_or_default(),
+                permission: AccessKeyPermissionView::FullAccess,
+            }),
+            QueryRequest::CallFunction {
+                account_id,
+                method_name,
+                ..
+            } if account_id == self.contract_id && method_name == "public_key" => {
+                QueryResponseKind::CallResult(CallResult {
+                    result: serde_json::to_vec(&self.signer.naj_public_key()).unwrap(),
+                    logs: vec![],
+                })
+            }
+            // The mock signer has a single root key, of version 0.
+            QueryRequest::CallFunction {
+                account_id,
+                method_name,
+                ..
+            } if account_id == self.contract_id && method_name == "latest_key_version" => {
+                QueryResponseKind::CallResult(CallResult {
+                    result: serde_json::to_vec(&0u32).unwrap(),
+                    logs: vec![],
+                })
+            }
+            QueryRequest::CallFunction {
+                account_id,
+                method_name,
+                ..
+            } if account_id == self.contract_id
+                && method_name == "experimental_signature_deposit"
+                && self.signature_deposit.is_some() =>
+            {
+                QueryResponseKind::CallResult(CallResult {
+                    result: serde_json::to_vec(&self.signature_deposit.unwrap().to_string())
+                        .unwrap(),
+                    logs: vec![],
+                })
+            }
+            QueryRequest::CallFunction { .. } => {
+                return Ok(json!({
+                    "error": "wasm execution failed with error: MethodResolveError(MethodNotFound)",
+                    "logs": [],
+                    "block_height": BLOCK_HEIGHT,
+                    "block_hash": block_hash(),
+                }))
+            }
+            request => {
+                return Ok(json!({
+                    "error": format!("unsupported query {:?}", request),
+                    "logs": [],
+                    "block_height": BLOCK_HEIGHT,
+                    "block_hash": block_hash(),
+                }))
+            }
+        };
+
+        Ok(serde_json::to_value(RpcQueryResponse {
+            kind,
+            block_height: BLOCK_HEIGHT,
+            block_hash: block_hash(),
+        })
+        .unwrap())
+    }
+
+    fn broadcast_tx_async(&mut self, params: Value) -> Result<Value, Value> {
+        let tx_hash = self.submit(&params[0])?;
+
+        Ok(json!(tx_hash))
+    }
+
+    /// Executes the transaction right away, so it is always final by the time `send_tx` answers.
+    fn send_tx(&mut self, params: Value) -> Result<Value, Value> {
+        let tx_hash = self.submit(&params["signed_tx_base64"])?;
+
+        if self.send_tx_timeout {
+            return Err(handler_error(RpcTransactionError::TimeoutError));
+        }
+
+        self.transaction_status(tx_hash, TxExecutionStatus::Final)
+    }
+
+    fn submit(&mut self, signed_tx_base64: &Value) -> Result<CryptoHash, Value> {
+        let signed_transaction = signed_tx_base64
+            .as_str()
+            .and_then(|encoded| near_primitives::serialize::from_base64(encoded).ok())
+            .and_then(|bytes| SignedTransaction::try_from_slice(&bytes).ok())
+            .ok_or_else(|| handler_error("invalid signed transaction"))?;
+
+        let tx_hash = signed_transaction.get_hash();
+        let outcome = self.execute(signed_transaction);
+        self.transactions.insert(tx_hash, outcome);
+
+        Ok(tx_hash)
+    }
+
+    fn tx(&mut self, params: Value) -> Result<Value, Value> {
+        let tx_hash: CryptoHash = serde_json::from_value(params["tx_hash"].clone())
+            .map_err(|e| handler_error(e.to_string()))?;
+
+        let polls = self.polls.entry(tx_hash).or_default();
+        let status = self
+            .execution_progress
+            .get(*polls)
+            .cloned()
+            .unwrap_or(TxExecutionStatus::Final);
+        *polls += 1;
+
+        self.transaction_status(tx_hash, status)
+    }
+
+    fn transaction_status(
+        &self,
+        tx_hash: CryptoHash,
+        status: TxExecutionStatus,
+    ) -> Result<Value, Value> {
+        match self.transactions.get(&tx_hash) {
+            Some(Ok(outcome)) => Ok(serde_json::to_value(RpcTransactionResponse {
+                final_execution_outcome: matches!(
+                    status,
+                    TxExecutionStatus::ExecutedOptimistic
+                        | TxExecutionStatus::Executed
+                        | TxExecutionStatus::Final
+                )
+                .then(|| FinalExecutionOutcomeViewEnum::FinalExecutionOutcome(outcome.clone())),
+                final_execution_status: status,
+            })
+            .unwrap()),
+            // Like nearcore, the invalid transaction error is only described in `data`.
+            Some(Err(err)) => {
+                let mut error = handler_error(RpcTransactionError::InvalidTransaction {
+                    context: err.clone(),
+                });
+                error["data"] = json!(ServerError::TxExecutionError(
+                    TxExecutionError::InvalidTxError(err.clone())
+                ));
+                Err(error)
+            }
+            None => Err(handler_error(RpcTransactionError::UnknownTransaction {
+                requested_transaction_hash: tx_hash,
+            })),
+        }
+    }
+
+    /// Validates the transaction like a node would and runs its `sign` calls.
+    fn execute(
+        &mut self,
+        signed_transaction: SignedTransaction,
+    ) -> Result<FinalExecutionOutcomeView, InvalidTxError> {
+        let tx_hash = signed_transaction.get_hash();
+        let transaction = &signed_transaction.transaction;
+
+        if !signed_transaction
+            .signature
+            .verify(tx_hash.as_ref(), &transaction.public_key)
+        {
+            return Err(InvalidTxError::InvalidSignature);
+        }
+
+        let key = (
+            transaction.signer_id.clone(),
+            transaction.public_key.clone(),
+        );
+        let ak_nonce = self
+            .access_key_nonces
+            .get(&key)
+            .copied()
+            .unwrap_or_default();
+        if transaction.nonce <= ak_nonce {
+            return Err(InvalidTxError::InvalidNonce {
+                tx_nonce: transaction.nonce,
+                ak_nonce,
+            });
+        }
+        self.access_key_nonces.insert(key, transaction.nonce);
+
+        let mut status = ExecutionStatusView::SuccessValue(vec![]);
+        for (index, action) in transaction.actions.iter().enumerate() {
+            status = match action {
+                Action::FunctionCall(function_call) => self
+                    .call_function(
+                        &transaction.signer_id,
+                        &transaction.receiver_id,
+                        function_call,
+                    )
+                    .map(ExecutionStatusView::SuccessValue)
+                    .unwrap_or_else(|kind| {
+                        ExecutionStatusView::Failure(TxExecutionError::ActionError(ActionError {
+                            index: Some(index as u64),
+                            kind,
+                        }))
+                    }),
+                _ => ExecutionStatusView::SuccessValue(vec![]),
+            };
+
+            if matches!(status, ExecutionStatusView::Failure(_)) {
+                break;
+            }
+        }
+
+        let receipt_id = CryptoHash::hash_bytes(tx_hash.as_ref());
+        let final_status = match &status {
+            ExecutionStatusView::SuccessValue(value) => {
+                FinalExecutionStatus::SuccessValue(value.clone())
+            }
+            ExecutionStatusView::Failure(err) => FinalExecutionStatus::Failure(err.clone()),
+            _ => FinalExecutionStatus::NotStarted,
+        };
+
+        Ok(FinalExecutionOutcomeView {
+            status: final_status,
+            transaction: SignedTransactionView::from(signed_transaction.clone()),
+            transaction_outcome: outcome_view(
+                tx_hash,
+                transaction.signer_id.clone(),
+                vec![receipt_id],
+                ExecutionStatusView::SuccessReceiptId(receipt_id),
+            ),
+            receipts_outcome: vec![outcome_view(
+                receipt_id,
+                transaction.receiver_id.clone(),
+                vec![],
+                status,
+            )],
+        })
+    }
+
+    fn call_function(
+        &mut self,
+        predecessor: &AccountId,
+        receiver_id: &AccountId,
+        function_call: &FunctionCallAction,
+    ) -> Result<Vec<u8>, ActionErrorKind> {
+        self.function_calls.push(function_call.clone());
+
+        if receiver_id != &self.contract_id || function_call.method_name != "sign" {
+            return Err(ActionErrorKind::FunctionCallError(
+                FunctionCallError::MethodResolveError(MethodResolveError::MethodNotFound),
+            ));
+        }
+
+        let panic = |message: String| {
+            ActionErrorKind::FunctionCallError(FunctionCallError::ExecutionError(format!(
+                "Smart contract panicked: {}",
+                message
+            )))
+        };
+
+        if let Some(required) = self.signature_deposit {
+            if function_call.deposit < required {
+                return Err(panic(format!(
+                    "Attached deposit is lower than required. Attached: {}, Required: {}.",
+                    function_call.deposit, required
+                )));
+            }
+        }
+
+        let args: Value =
+            serde_json::from_slice(&function_call.args).map_err(|e| panic(e.to_string()))?;
+        let request: SignRequest =
+            serde_json::from_value(args["request"].clone()).map_err(|e| panic(e.to_string()))?;
+        let response = self
+            .signer
+            .sign(predecessor, &request)
+            .map_err(|e| panic(e.to_string()))?;
+
+        Ok(serde_json::to_vec(&response).unwrap())
+    }
+}
+
+fn outcome_view(
+    id: CryptoHash,
+    executor_id: AccountId,
+    receipt_ids: Vec<CryptoHash>,
+    status: ExecutionStatusView,
+) -> ExecutionOutcomeWithIdView {
+    ExecutionOutcomeWithIdView {
+        proof: vec![],
+        block_hash: block_hash(),
+        id,
+        outcome: ExecutionOutcomeView {
+            logs: vec![],
+            receipt_ids,
+            gas_burnt: 0,
+            tokens_burnt: 0,
+            executor_id,
+            status,
+            metadata: ExecutionMetadataView::default(),
+        },
+    }
+}
@@ -0,0 +1,40 @@
+//! An in-process stand-in for a remote NEAR signing service, as used by `RemoteSigner`.
+
+use near_crypto::{InMemorySigner, Signer};
+use near_primitives::hash::CryptoHash;
+use serde_json::{json, Value};
+
+use crate::http::serve;
+
+pub struct FakeSigningService {
+    url: String,
+}
+
+impl FakeSigningService {
+    /// Starts the service on a random local port, signing every requested hash with `signer`.
+    /// Requests for another account or public key are refused.
+    pub async fn start(signer: InMemorySigner) -> Self {
+        let url = serve(move |body| handle_request(&signer, body)).await;
+
+        Self { url }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+fn handle_request(signer: &InMemorySigner, body: &[u8]) -> Value {
+    let request: Value = serde_json::from_slice(body).unwrap_or_default();
+
+    if request["account_id"] != json!(signer.account_id)
+        || request["public_key"] != json!(signer.public_key)
+    {
+        return json!({ "error": "unknown key" });
+    }
+
+    match serde_json::from_value::<CryptoHash>(request["hash"].clone()) {
+        Ok(hash) => json!({ "signature": signer.sign(hash.as_ref()) }),
+        Err(e) => json!({ "error": e.to_string() }),
+    }
+}
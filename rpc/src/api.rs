@@ -1,38 +1,48 @@
-use near_crypto::InMemorySigner;
-use near_jsonrpc_client::{methods, JsonRpcClient};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use near_jsonrpc_client::auth::ApiKey;
+use near_jsonrpc_client::errors::{JsonRpcError, JsonRpcServerError};
+use near_jsonrpc_client::header::{HeaderName, HeaderValue};
+use near_jsonrpc_client::{methods, JsonRpcClient, MethodCallResult};
 use near_jsonrpc_primitives::types::query::QueryResponseKind;
-use near_jsonrpc_primitives::types::transactions::{RpcTransactionError, TransactionInfo};
+use near_jsonrpc_primitives::types::transactions::{
+    RpcTransactionError, RpcTransactionResponse, TransactionInfo,
+};
 use near_primitives::hash::CryptoHash;
-use near_primitives::transaction::{Action, FunctionCallAction, Transaction};
+use near_primitives::transaction::{Action, FunctionCallAction, SignedTransaction, Transaction};
 use near_primitives::types::{BlockReference, Finality, FunctionArgs};
-use near_primitives::views::{FinalExecutionOutcomeViewEnum, QueryRequest, TxExecutionStatus};
+use near_primitives::views::{QueryRequest, TxExecutionStatus};
 use near_sdk::AccountId;
-
-use tokio::time;
+use rand::Rng;
+use tokio::time::{self, Duration};
+use utils::signer::NearSigner;
 use utils::types::NearNetwork;
 
-pub async fn get_current_nonce(
-    client: &near_jsonrpc_client::JsonRpcClient,
-    signer: &InMemorySigner,
-) -> Result<u64, Box<dyn std::error::Error>> {
+use crate::error::{ChainSignatureError, Result};
+
+pub async fn get_current_nonce(client: &NearRpcClient, signer: &dyn NearSigner) -> Result<u64> {
     let access_key_query_response = client
         .call(methods::query::RpcQueryRequest {
             block_reference: BlockReference::latest(),
             request: near_primitives::views::QueryRequest::ViewAccessKey {
-                account_id: signer.account_id.clone(),
-                public_key: signer.public_key.clone(),
+                account_id: signer.account_id().clone(),
+                public_key: signer.public_key().clone(),
             },
         })
         .await?;
 
     match access_key_query_response.kind {
         QueryResponseKind::AccessKey(access_key) => Ok(access_key.nonce),
-        _ => Err("failed to extract current nonce".into()),
+        _ => Err(ChainSignatureError::UnexpectedResponse(
+            "failed to extract current nonce".to_string(),
+        )),
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn create_function_call_transaction(
-    signer: &InMemorySigner,
+    signer: &dyn NearSigner,
     receiver_id: AccountId,
     block_hash: CryptoHash,
     nonce: u64,
@@ -42,8 +52,8 @@ pub fn create_function_call_transaction(
     deposit: u128,
 ) -> Transaction {
     Transaction {
-        signer_id: signer.account_id.clone(),
-        public_key: signer.public_key.clone(),
+        signer_id: signer.account_id().clone(),
+        public_key: signer.public_key().clone(),
         nonce,
         receiver_id,
         block_hash,
@@ -56,57 +66,277 @@ pub fn create_function_call_transaction(
     }
 }
 
+type StatusCallback = Arc<dyn Fn(TxExecutionStatus) + Send + Sync>;
+
+/// How [`send_transaction`] submits a signed transaction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SubmitMode {
+    /// `broadcast_tx_async`, then poll the status with [`wait_for_transaction`].
+    #[default]
+    BroadcastAsync,
+    /// `send_tx` with `wait_until` set to the target finality, which answers with the outcome in
+    /// a single round trip. If the RPC node gives up waiting first, the status is polled instead.
+    SendTx,
+}
+
+/// How [`send_transaction`] submits a transaction and [`wait_for_transaction`] polls for its
+/// status.
+///
+/// Polls start `initial_interval` apart and back off exponentially up to `max_interval`, each
+/// randomized by up to `jitter` (a fraction of the interval) so that many waiting clients don't
+/// poll in lockstep. The whole wait is bounded by `timeout`. Every setting has a builder and is
+/// sanitized where it is used, so no combination of values panics.
+#[derive(Clone)]
+pub struct WaitPolicy {
+    finality: TxExecutionStatus,
+    initial_interval: Duration,
+    max_interval: Duration,
+    multiplier: f64,
+    jitter: f64,
+    timeout: Duration,
+    submit_mode: SubmitMode,
+    on_status: Option<StatusCallback>,
+}
+
+impl Default for WaitPolicy {
+    fn default() -> Self {
+        Self {
+            finality: TxExecutionStatus::Executed,
+            initial_interval: Duration::from_millis(250),
+            max_interval: Duration::from_secs(5),
+            multiplier: 2.0,
+            jitter: 0.2,
+            timeout: Duration::from_secs(300),
+            submit_mode: SubmitMode::default(),
+            on_status: None,
+        }
+    }
+}
+
+impl WaitPolicy {
+    /// The status to wait for. Only `ExecutedOptimistic`, `Executed` and `Final` come with an
+    /// execution outcome.
+    pub fn finality(&self) -> &TxExecutionStatus {
+        &self.finality
+    }
+
+    pub fn with_finality(mut self, finality: TxExecutionStatus) -> Self {
+        self.finality = finality;
+        self
+    }
+
+    pub fn with_backoff(mut self, initial_interval: Duration, max_interval: Duration) -> Self {
+        self.initial_interval = initial_interval;
+        self.max_interval = max_interval;
+        self
+    }
+
+    /// Sets the factor each poll interval grows by. Factors below 1, or NaN, are treated as 1.
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Sets the jitter, clamped to `0.0..=1.0`. NaN disables it.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_submit_mode(mut self, submit_mode: SubmitMode) -> Self {
+        self.submit_mode = submit_mode;
+        self
+    }
+
+    /// Calls `on_status` every time the transaction reaches a new status, including the final
+    /// one. With [`SubmitMode::SendTx`], only the final status is reported unless the node times
+    /// out and the status is polled.
+    pub fn on_status(
+        mut self,
+        on_status: impl Fn(TxExecutionStatus) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_status = Some(Arc::new(on_status));
+        self
+    }
+
+    /// The delay before poll number `attempt + 1`.
+    fn interval(&self, attempt: u32) -> Duration {
+        // NaN fails both comparisons, which leaves no backoff and no jitter.
+        let multiplier = if self.multiplier >= 1.0 {
+            self.multiplier
+        } else {
+            1.0
+        };
+        let jitter = if self.jitter > 0.0 {
+            self.jitter.min(1.0)
+        } else {
+            0.0
+        };
+
+        let backoff = self.initial_interval.as_secs_f64() * multiplier.powi(attempt as i32);
+        let interval = backoff.min(self.max_interval.as_secs_f64());
+        let jitter = rand::thread_rng().gen_range(-jitter..=jitter);
+
+        // Too long for a `Duration`, e.g. with a `max_interval` of `Duration::MAX` and a positive
+        // jitter.
+        Duration::try_from_secs_f64(interval * (1.0 + jitter)).unwrap_or(self.max_interval)
+    }
+}
+
+/// Whether a transaction with `status` satisfies the `target` finality. The statuses are not
+/// totally ordered: `IncludedFinal` does not imply `ExecutedOptimistic` or the other way around.
+fn has_reached(status: &TxExecutionStatus, target: &TxExecutionStatus) -> bool {
+    use TxExecutionStatus::*;
+
+    match target {
+        None => true,
+        Included => !matches!(status, None),
+        ExecutedOptimistic => matches!(status, ExecutedOptimistic | Executed | Final),
+        IncludedFinal => matches!(status, IncludedFinal | Executed | Final),
+        Executed => matches!(status, Executed | Final),
+        Final => matches!(status, Final),
+    }
+}
+
+/// Submits `signed_transaction` according to `policy.submit_mode` and waits until it reaches
+/// `policy.finality`. Returns the transaction hash along with its status, whichever way the
+/// outcome was obtained.
+pub async fn send_transaction(
+    client: &NearRpcClient,
+    signed_transaction: SignedTransaction,
+    signer: &dyn NearSigner,
+    policy: &WaitPolicy,
+) -> Result<(CryptoHash, RpcTransactionResponse)> {
+    let tx_hash = match policy.submit_mode {
+        SubmitMode::BroadcastAsync => {
+            client
+                .call(methods::broadcast_tx_async::RpcBroadcastTxAsyncRequest {
+                    signed_transaction,
+                })
+                .await?
+        }
+        SubmitMode::SendTx => {
+            let tx_hash = signed_transaction.get_hash();
+            let sent_at = time::Instant::now();
+            let request = methods::send_tx::RpcSendTransactionRequest {
+                signed_transaction,
+                wait_until: policy.finality.clone(),
+            };
+
+            let response = time::timeout(policy.timeout, client.call(request))
+                .await
+                .map_err(|_| ChainSignatureError::Timeout(policy.timeout))?;
+
+            match response {
+                Ok(response) => {
+                    if let Some(on_status) = &policy.on_status {
+                        on_status(response.final_execution_status.clone());
+                    }
+                    return Ok((tx_hash, response));
+                }
+                Err(err) => match err.handler_error() {
+                    // The transaction was accepted but the node stopped waiting for it.
+                    Some(RpcTransactionError::TimeoutError) => {}
+                    Some(RpcTransactionError::InvalidTransaction { context }) => {
+                        return Err(context.clone().into())
+                    }
+                    _ => return Err(err.into()),
+                },
+            }
+
+            let remaining = policy.timeout.saturating_sub(sent_at.elapsed());
+            let response = wait_for_transaction(
+                client,
+                tx_hash,
+                signer,
+                &policy.clone().with_timeout(remaining),
+            )
+            .await
+            .map_err(|err| match err {
+                ChainSignatureError::Timeout(_) => ChainSignatureError::Timeout(policy.timeout),
+                err => err,
+            })?;
+
+            return Ok((tx_hash, response));
+        }
+    };
+
+    let response = wait_for_transaction(client, tx_hash, signer, policy).await?;
+    Ok((tx_hash, response))
+}
+
+/// Polls the status of `tx_hash` until it reaches `policy.finality`, reporting every new status
+/// to the policy's callback. Fails with [`ChainSignatureError::Timeout`] once `policy.timeout`
+/// has passed, even if a request is still in flight.
 pub async fn wait_for_transaction(
-    client: &near_jsonrpc_client::JsonRpcClient,
+    client: &NearRpcClient,
     tx_hash: CryptoHash,
-    signer: &InMemorySigner,
-    timeout: time::Duration,
-) -> Result<FinalExecutionOutcomeViewEnum, Box<dyn std::error::Error>> {
-    let sent_at = time::Instant::now();
-
-    loop {
-        let response = client
-            .call(methods::tx::RpcTransactionStatusRequest {
-                transaction_info: TransactionInfo::TransactionId {
-                    tx_hash,
-                    sender_account_id: signer.account_id.clone(),
+    signer: &dyn NearSigner,
+    policy: &WaitPolicy,
+) -> Result<RpcTransactionResponse> {
+    let poll = async {
+        let mut last_status = Option::None;
+        let mut attempt = 0;
+
+        loop {
+            let response = client
+                .call(methods::tx::RpcTransactionStatusRequest {
+                    transaction_info: TransactionInfo::TransactionId {
+                        tx_hash,
+                        sender_account_id: signer.account_id().clone(),
+                    },
+                    // Answered right away, so that intermediate statuses can be reported.
+                    wait_until: TxExecutionStatus::None,
+                })
+                .await;
+
+            match response {
+                Err(err) => match err.handler_error() {
+                    Some(
+                        RpcTransactionError::TimeoutError
+                        | RpcTransactionError::UnknownTransaction { .. },
+                    ) => {}
+                    Some(RpcTransactionError::InvalidTransaction { context }) => {
+                        return Err(context.clone().into())
+                    }
+                    _ => return Err(err.into()),
                 },
-                wait_until: TxExecutionStatus::Executed,
-            })
-            .await;
-        let received_at = time::Instant::now();
-        let delta = received_at - sent_at;
-
-        if delta > timeout {
-            return Err("time limit exceeded for the transaction to be recognized".into());
-        }
+                Ok(response) => {
+                    let status = response.final_execution_status.clone();
+                    if last_status.as_ref() != Some(&status) {
+                        if let Some(on_status) = &policy.on_status {
+                            on_status(status.clone());
+                        }
+                        last_status = Some(status.clone());
+                    }
 
-        match response {
-            Err(err) => match err.handler_error() {
-                Some(
-                    RpcTransactionError::TimeoutError
-                    | RpcTransactionError::UnknownTransaction { .. },
-                ) => {
-                    time::sleep(time::Duration::from_secs(10)).await;
-                    continue;
-                }
-                _ => return Err(err.into()),
-            },
-            Ok(response) => {
-                if let Some(outcome) = response.final_execution_outcome {
-                    return Ok(outcome);
+                    if has_reached(&status, &policy.finality) {
+                        return Ok(response);
+                    }
                 }
             }
+
+            time::sleep(policy.interval(attempt)).await;
+            attempt += 1;
         }
-    }
+    };
+
+    time::timeout(policy.timeout, poll)
+        .await
+        .map_err(|_| ChainSignatureError::Timeout(policy.timeout))?
 }
 
 pub async fn call_view_function(
-    client: &near_jsonrpc_client::JsonRpcClient,
+    client: &NearRpcClient,
     contract_id: AccountId,
     method_name: String,
     args: FunctionArgs,
-) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+) -> Result<Vec<u8>> {
     let request = methods::query::RpcQueryRequest {
         block_reference: BlockReference::Finality(Finality::Final),
         request: QueryRequest::CallFunction {
@@ -121,13 +351,13 @@ pub async fn call_view_function(
     if let QueryResponseKind::CallResult(result) = response.kind {
         Ok(result.result)
     } else {
-        Err("Unexpected response kind".into())
+        Err(ChainSignatureError::UnexpectedResponse(
+            "expected a function call result".to_string(),
+        ))
     }
 }
 
-pub async fn get_latest_block_hash(
-    client: &near_jsonrpc_client::JsonRpcClient,
-) -> Result<CryptoHash, Box<dyn std::error::Error>> {
+pub async fn get_latest_block_hash(client: &NearRpcClient) -> Result<CryptoHash> {
     let request = methods::block::RpcBlockRequest {
         block_reference: BlockReference::Finality(Finality::Final),
     };
@@ -136,11 +366,220 @@ pub async fn get_latest_block_hash(
     Ok(response.header.hash)
 }
 
-pub fn get_near_client(network: NearNetwork) -> JsonRpcClient {
-    let rpc_url = match network {
-        NearNetwork::Mainnet => "https://rpc.mainnet.near.org",
-        NearNetwork::Testnet => "https://rpc.testnet.near.org",
-    };
+pub fn get_near_client(network: NearNetwork) -> Result<NearRpcClient> {
+    NearRpcClient::new(&network)
+}
+
+/// A NEAR JSON-RPC client over one or more endpoints.
+///
+/// Requests go to the endpoint that last answered and fail over to the next one when it is
+/// unreachable, rate limited or returns a server-side error. Handler and request validation
+/// errors are returned as is, since another node would answer the same.
+#[derive(Clone)]
+pub struct NearRpcClient {
+    clients: Vec<JsonRpcClient>,
+    preferred: Arc<AtomicUsize>,
+}
+
+impl NearRpcClient {
+    pub fn new(network: &NearNetwork) -> Result<Self> {
+        let (rpc_url, fallback_urls, headers, api_key) = match network {
+            NearNetwork::Mainnet => ("https://rpc.mainnet.near.org", &[][..], None, None),
+            NearNetwork::Testnet => ("https://rpc.testnet.near.org", &[][..], None, None),
+            NearNetwork::Localnet => ("http://localhost:3030", &[][..], None, None),
+            NearNetwork::Custom {
+                rpc_url,
+                fallback_urls,
+                headers,
+                api_key,
+            } => (
+                rpc_url.as_str(),
+                fallback_urls.as_slice(),
+                Some(headers),
+                api_key.as_ref(),
+            ),
+        };
+
+        let mut clients = vec![];
+        for url in std::iter::once(rpc_url).chain(fallback_urls.iter().map(String::as_str)) {
+            let mut client = JsonRpcClient::connect(url);
+
+            for (name, value) in headers.into_iter().flatten() {
+                let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| {
+                    ChainSignatureError::InvalidNetworkConfig(format!("header {}: {}", name, e))
+                })?;
+                let value = HeaderValue::from_str(value).map_err(|e| {
+                    ChainSignatureError::InvalidNetworkConfig(format!("header {}: {}", name, e))
+                })?;
+                client.headers_mut().insert(name, value);
+            }
+
+            if let Some(api_key) = api_key {
+                client = client.header(ApiKey::new(api_key).map_err(|e| {
+                    ChainSignatureError::InvalidNetworkConfig(format!("API key: {}", e))
+                })?);
+            }
+
+            clients.push(client);
+        }
+
+        Ok(Self::from_clients(clients))
+    }
+
+    /// Builds a client over already configured endpoints, tried in order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `clients` is empty.
+    pub fn from_clients(clients: Vec<JsonRpcClient>) -> Self {
+        assert!(
+            !clients.is_empty(),
+            "at least one NEAR RPC endpoint is required"
+        );
+
+        Self {
+            clients,
+            preferred: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn server_addrs(&self) -> impl Iterator<Item = &str> {
+        self.clients.iter().map(JsonRpcClient::server_addr)
+    }
+
+    pub async fn call<M>(&self, method: M) -> MethodCallResult<M::Response, M::Error>
+    where
+        M: methods::RpcMethod,
+    {
+        let preferred = self.preferred.load(Ordering::Relaxed);
+        let mut last_error = None;
+
+        for offset in 0..self.clients.len() {
+            let index = (preferred + offset) % self.clients.len();
+            match self.clients[index].call(&method).await {
+                Err(err) if is_endpoint_failure(&err) => last_error = Some(err),
+                response => {
+                    self.preferred.store(index, Ordering::Relaxed);
+                    return response;
+                }
+            }
+        }
 
-    JsonRpcClient::connect(rpc_url)
+        Err(last_error.expect("at least one endpoint was tried"))
+    }
+}
+
+impl From<JsonRpcClient> for NearRpcClient {
+    fn from(client: JsonRpcClient) -> Self {
+        Self::from_clients(vec![client])
+    }
+}
+
+fn is_endpoint_failure<E>(err: &JsonRpcError<E>) -> bool {
+    matches!(
+        err,
+        JsonRpcError::TransportError(_)
+            | JsonRpcError::ServerError(
+                JsonRpcServerError::InternalError { .. }
+                    | JsonRpcServerError::ResponseStatusError(_)
+            )
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock_signer::{server::FakeNearRpc, MockSigner};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_backoff_is_capped_and_jittered() {
+        let policy = WaitPolicy::default()
+            .with_backoff(Duration::from_millis(100), Duration::from_secs(1))
+            .with_jitter(0.5);
+
+        for attempt in 0..100 {
+            let expected = (100.0 * 2f64.powi(attempt)).min(1000.0);
+            let interval = policy.interval(attempt as u32).as_secs_f64() * 1000.0;
+            assert!(interval >= expected * 0.5 - 1e-6 && interval <= expected * 1.5 + 1e-6);
+        }
+
+        let policy = policy.with_jitter(0.0);
+        assert_eq!(policy.interval(1), Duration::from_millis(200));
+
+        // Out of range values neither panic nor shrink the interval.
+        for multiplier in [-2.0, 0.5, f64::NAN] {
+            let policy = policy.clone().with_multiplier(multiplier);
+            assert_eq!(policy.interval(3), Duration::from_millis(100));
+        }
+        let policy = policy.with_jitter(f64::NAN);
+        assert_eq!(policy.interval(1), Duration::from_millis(200));
+
+        let policy = WaitPolicy::default()
+            .with_backoff(Duration::from_secs(1), Duration::MAX)
+            .with_multiplier(f64::INFINITY)
+            .with_jitter(1.0);
+        // Jittered intervals beyond `Duration::MAX` don't panic.
+        for attempt in 1..20 {
+            policy.interval(attempt);
+        }
+    }
+
+    #[test]
+    fn test_finality_is_partially_ordered() {
+        use TxExecutionStatus::*;
+
+        assert!(has_reached(&Final, &Executed));
+        assert!(has_reached(&Executed, &IncludedFinal));
+        assert!(has_reached(&Executed, &ExecutedOptimistic));
+        assert!(!has_reached(&IncludedFinal, &ExecutedOptimistic));
+        assert!(!has_reached(&ExecutedOptimistic, &IncludedFinal));
+        assert!(!has_reached(&None, &Included));
+    }
+
+    #[tokio::test]
+    async fn test_failover_to_fallback_url() -> Result<()> {
+        let server =
+            FakeNearRpc::start(MockSigner::default(), "signer.test.near".parse().unwrap()).await;
+        let unreachable_url = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+
+        let client = get_near_client(NearNetwork::Custom {
+            rpc_url: unreachable_url,
+            fallback_urls: vec![server.url().to_string()],
+            headers: HashMap::from([("x-request-source".to_string(), "tests".to_string())]),
+            api_key: Some("test-key".to_string()),
+        })?;
+        assert_eq!(client.server_addrs().count(), 2);
+
+        get_latest_block_hash(&client).await?;
+        assert_eq!(client.preferred.load(Ordering::Relaxed), 1);
+        get_latest_block_hash(&client).await?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_custom_network_is_rejected() {
+        let network = NearNetwork::Custom {
+            rpc_url: "http://localhost:3030".to_string(),
+            fallback_urls: vec![],
+            headers: HashMap::from([("bad header".to_string(), "value".to_string())]),
+            api_key: None,
+        };
+
+        assert!(matches!(
+            get_near_client(network),
+            Err(ChainSignatureError::InvalidNetworkConfig(_))
+        ));
+        assert_eq!(
+            get_near_client(NearNetwork::Localnet)
+                .unwrap()
+                .server_addrs()
+                .collect::<Vec<_>>(),
+            vec!["http://localhost:3030"]
+        );
+    }
 }
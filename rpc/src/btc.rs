@@ -0,0 +1,908 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use bitcoin::{
+    absolute::LockTime,
+    consensus::encode::serialize_hex,
+    ecdsa,
+    hashes::Hash,
+    script::PushBytes,
+    secp256k1,
+    sighash::{EcdsaSighashType, SighashCache},
+    transaction::Version,
+    Address, Amount, CompressedPublicKey, Network, OutPoint, PublicKey, ScriptBuf, Sequence,
+    Transaction, TxIn, TxOut, Txid, Witness,
+};
+use ethers_core::k256::ecdsa::VerifyingKey;
+use near_primitives::hash::CryptoHash;
+use near_sdk::AccountId;
+use serde::Deserialize;
+use utils::{
+    error::KdfError,
+    kdf::btc_address_from_public_key,
+    types::{
+        BitcoinAddressType, BitcoinNetwork, DerivationPath, KeyVersion, NearAuthentication,
+        SignatureResponse,
+    },
+};
+
+use crate::{
+    error::{ChainSignatureError, Result},
+    sign::{SignConfig, SignConfigBuilder},
+};
+
+/// Outputs below this value are rejected by the default relay policy.
+const DUST_LIMIT: u64 = 546;
+/// Confirmation target used when asking the Esplora API for a fee rate.
+const FEE_TARGET_BLOCKS: u16 = 6;
+
+/// Approximate virtual size contributed by a single signed input of the given type.
+fn input_vsize(address_type: BitcoinAddressType) -> u64 {
+    match address_type {
+        BitcoinAddressType::P2PKH => 148,
+        BitcoinAddressType::P2SHP2WPKH => 91,
+        BitcoinAddressType::P2WPKH => 68,
+        BitcoinAddressType::P2TR => 58,
+    }
+}
+
+fn bitcoin_error(err: impl std::fmt::Display) -> ChainSignatureError {
+    ChainSignatureError::Bitcoin(err.to_string())
+}
+
+fn bitcoin_network(network: Network) -> BitcoinNetwork {
+    match network {
+        Network::Bitcoin => BitcoinNetwork::Mainnet,
+        Network::Signet => BitcoinNetwork::Signet,
+        Network::Regtest => BitcoinNetwork::Regtest,
+        _ => BitcoinNetwork::Testnet,
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct UtxoStatus {
+    pub confirmed: bool,
+    pub block_height: Option<u64>,
+}
+
+/// An unspent output as returned by the Esplora `/address/:address/utxo` endpoint.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Utxo {
+    pub txid: Txid,
+    pub vout: u32,
+    pub value: u64,
+    pub status: UtxoStatus,
+}
+
+/// A transaction signed by [`BTC::sign_transaction`].
+#[derive(Debug, Clone)]
+pub struct SignedTransaction {
+    pub transaction: Transaction,
+    /// The NEAR transactions that requested the signatures, one per input.
+    pub near_tx_hashes: Vec<CryptoHash>,
+}
+
+/// A transaction sent by [`BTC::handle_transaction`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentTransaction {
+    pub txid: Txid,
+    /// The NEAR transactions that requested the signatures, one per input.
+    pub near_tx_hashes: Vec<CryptoHash>,
+}
+
+pub struct BTC {
+    esplora_url: String,
+    http_client: reqwest::Client,
+    network: Network,
+    sign_config: SignConfig,
+}
+
+impl BTC {
+    pub fn new(
+        esplora_url: &str,
+        network: Network,
+        near_authentication: NearAuthentication,
+        contract: AccountId,
+        key_version: KeyVersion,
+    ) -> Result<Self> {
+        Ok(Self {
+            esplora_url: esplora_url.trim_end_matches('/').to_string(),
+            http_client: reqwest::Client::new(),
+            network,
+            sign_config: SignConfig::new(near_authentication, contract, key_version)?,
+        })
+    }
+
+    pub async fn derive_public_key(
+        &self,
+        signer_id: &str,
+        path: &DerivationPath,
+    ) -> Result<PublicKey> {
+        let child_public_key = self.sign_config.derive_public_key(signer_id, path).await?;
+
+        PublicKey::from_slice(child_public_key.to_encoded_point(true).as_bytes())
+            .map_err(bitcoin_error)
+    }
+
+    pub async fn derive_address(
+        &self,
+        signer_id: &str,
+        path: &DerivationPath,
+        address_type: BitcoinAddressType,
+    ) -> Result<Address> {
+        let public_key = self.derive_public_key(signer_id, path).await?;
+        self.address_for(&public_key, address_type)
+    }
+
+    fn address_for(
+        &self,
+        public_key: &PublicKey,
+        address_type: BitcoinAddressType,
+    ) -> Result<Address> {
+        let verifying_key =
+            VerifyingKey::from_sec1_bytes(&public_key.to_bytes()).map_err(KdfError::from)?;
+        let address = btc_address_from_public_key(
+            &verifying_key,
+            address_type,
+            bitcoin_network(self.network),
+        )?;
+
+        Address::from_str(&address)
+            .and_then(|address| address.require_network(self.network))
+            .map_err(bitcoin_error)
+    }
+
+    pub async fn get_utxos(&self, address: &Address) -> Result<Vec<Utxo>> {
+        let utxos = self
+            .http_client
+            .get(format!("{}/address/{}/utxo", self.esplora_url, address))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Vec<Utxo>>()
+            .await?;
+
+        Ok(utxos)
+    }
+
+    pub async fn get_balance(&self, address: &Address) -> Result<u64> {
+        let utxos = self.get_utxos(address).await?;
+        Ok(utxos.iter().map(|utxo| utxo.value).sum())
+    }
+
+    /// Returns the fee rate in sat/vB for confirmation within [`FEE_TARGET_BLOCKS`] blocks.
+    pub async fn get_fee_rate(&self) -> Result<u64> {
+        let estimates = self
+            .http_client
+            .get(format!("{}/fee-estimates", self.esplora_url))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<HashMap<String, f64>>()
+            .await?;
+
+        let fee_rate = estimates
+            .get(&FEE_TARGET_BLOCKS.to_string())
+            .copied()
+            .ok_or_else(|| {
+                bitcoin_error(format!(
+                    "fee estimate for {} blocks not available",
+                    FEE_TARGET_BLOCKS
+                ))
+            })?;
+
+        Ok((fee_rate.ceil() as u64).max(1))
+    }
+
+    /// Selects confirmed UTXOs (largest first) and builds an unsigned transaction paying
+    /// `amount` satoshis to `to`, with the remainder returned to `change` when above dust.
+    /// Amounts below the dust limit are rejected, since nodes would not relay them.
+    pub fn build_transaction(
+        utxos: &[Utxo],
+        to: &Address,
+        amount: u64,
+        change: &Address,
+        fee_rate: u64,
+        address_type: BitcoinAddressType,
+    ) -> Result<(Transaction, Vec<Utxo>)> {
+        if amount < DUST_LIMIT {
+            return Err(bitcoin_error(format!(
+                "amount of {} sat is below the dust limit of {} sat",
+                amount, DUST_LIMIT
+            )));
+        }
+
+        let mut candidates: Vec<&Utxo> = utxos.iter().filter(|u| u.status.confirmed).collect();
+        candidates.sort_by_key(|utxo| std::cmp::Reverse(utxo.value));
+
+        let mut selected = Vec::new();
+        let mut total = 0;
+        let mut fee = 0;
+
+        for utxo in candidates {
+            selected.push(utxo.clone());
+            total += utxo.value;
+            fee = fee_rate * (11 + input_vsize(address_type) * selected.len() as u64 + 2 * 34);
+
+            if total >= amount + fee {
+                break;
+            }
+        }
+
+        if total < amount + fee {
+            return Err(ChainSignatureError::InsufficientFunds {
+                available: total,
+                required: amount + fee,
+            });
+        }
+
+        let mut output = vec![TxOut {
+            value: Amount::from_sat(amount),
+            script_pubkey: to.script_pubkey(),
+        }];
+
+        let change_value = total - amount - fee;
+        if change_value >= DUST_LIMIT {
+            output.push(TxOut {
+                value: Amount::from_sat(change_value),
+                script_pubkey: change.script_pubkey(),
+            });
+        }
+
+        let input = selected
+            .iter()
+            .map(|utxo| TxIn {
+                previous_output: OutPoint::new(utxo.txid, utxo.vout),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            })
+            .collect();
+
+        let transaction = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input,
+            output,
+        };
+
+        Ok((transaction, selected))
+    }
+
+    /// Computes the sighash of every input, has it signed by the MPC contract and
+    /// attaches the resulting signature as a witness or scriptSig. `utxos` are the outputs
+    /// spent by the inputs, in the same order.
+    pub async fn sign_transaction(
+        &self,
+        mut transaction: Transaction,
+        utxos: &[Utxo],
+        public_key: &PublicKey,
+        address_type: BitcoinAddressType,
+        path: DerivationPath,
+    ) -> Result<SignedTransaction> {
+        if utxos.len() != transaction.input.len() {
+            return Err(bitcoin_error("expected one UTXO per input"));
+        }
+        if address_type == BitcoinAddressType::P2TR {
+            return Err(bitcoin_error(
+                "Taproot inputs require Schnorr signatures, which the MPC contract does not produce",
+            ));
+        }
+
+        let script_pubkey = self.address_for(public_key, address_type)?.script_pubkey();
+        let witness_script = ScriptBuf::new_p2wpkh(
+            &CompressedPublicKey::try_from(*public_key)
+                .map_err(bitcoin_error)?
+                .wpubkey_hash(),
+        );
+
+        let verifying_key =
+            VerifyingKey::from_sec1_bytes(&public_key.to_bytes()).map_err(KdfError::from)?;
+
+        // The sighashes don't cover the scriptSigs or witnesses of the other inputs, so they can
+        // all be computed up front and signed in a single batch.
+        let mut sighashes = Vec::with_capacity(utxos.len());
+        for (index, utxo) in utxos.iter().enumerate() {
+            let mut sighash_cache = SighashCache::new(&transaction);
+            let sighash: [u8; 32] = match address_type {
+                BitcoinAddressType::P2PKH => sighash_cache
+                    .legacy_signature_hash(index, &script_pubkey, EcdsaSighashType::All.to_u32())
+                    .map_err(bitcoin_error)?
+                    .to_byte_array(),
+                _ => sighash_cache
+                    .p2wpkh_signature_hash(
+                        index,
+                        &witness_script,
+                        Amount::from_sat(utxo.value),
+                        EcdsaSighashType::All,
+                    )
+                    .map_err(bitcoin_error)?
+                    .to_byte_array(),
+            };
+            sighashes.push(sighash);
+        }
+
+        let outcomes = self.sign_config.sign_batch(&sighashes, path).await;
+
+        let mut near_tx_hashes = Vec::with_capacity(outcomes.len());
+        for (index, (outcome, sighash)) in outcomes.into_iter().zip(&sighashes).enumerate() {
+            let outcome = outcome?;
+            near_tx_hashes.push(outcome.tx_hash);
+            let signature = outcome.signature.normalize_s();
+            signature.verify(&verifying_key, sighash)?;
+
+            let signature = to_bitcoin_signature(&signature)?;
+
+            match address_type {
+                BitcoinAddressType::P2PKH => {
+                    transaction.input[index].script_sig = ScriptBuf::builder()
+                        .push_slice(signature.serialize())
+                        .push_key(public_key)
+                        .into_script();
+                }
+                BitcoinAddressType::P2SHP2WPKH => {
+                    transaction.input[index].script_sig = ScriptBuf::builder()
+                        .push_slice(
+                            <&PushBytes>::try_from(witness_script.as_bytes())
+                                .map_err(bitcoin_error)?,
+                        )
+                        .into_script();
+                    transaction.input[index].witness =
+                        Witness::p2wpkh(&signature, &public_key.inner);
+                }
+                _ => {
+                    transaction.input[index].witness =
+                        Witness::p2wpkh(&signature, &public_key.inner);
+                }
+            }
+        }
+
+        Ok(SignedTransaction {
+            transaction,
+            near_tx_hashes,
+        })
+    }
+
+    pub async fn send_signed_transaction(&self, transaction: &Transaction) -> Result<Txid> {
+        let response = self
+            .http_client
+            .post(format!("{}/tx", self.esplora_url))
+            .body(serialize_hex(transaction))
+            .send()
+            .await?;
+
+        let status = response.status();
+        let body = response.text().await?;
+
+        if !status.is_success() {
+            return Err(bitcoin_error(format!(
+                "failed to broadcast transaction: {}",
+                body
+            )));
+        }
+
+        Txid::from_str(body.trim()).map_err(bitcoin_error)
+    }
+
+    pub async fn handle_transaction(
+        &self,
+        to: &str,
+        amount: u64,
+        path: DerivationPath,
+        address_type: BitcoinAddressType,
+    ) -> Result<SentTransaction> {
+        let to = Address::from_str(to)
+            .and_then(|address| address.require_network(self.network))
+            .map_err(bitcoin_error)?;
+        let public_key = self
+            .derive_public_key(self.sign_config.account_id().as_str(), &path)
+            .await?;
+        let from = self.address_for(&public_key, address_type)?;
+
+        let utxos = self.get_utxos(&from).await?;
+        let fee_rate = self.get_fee_rate().await?;
+        let (transaction, selected) =
+            Self::build_transaction(&utxos, &to, amount, &from, fee_rate, address_type)?;
+
+        let signed = self
+            .sign_transaction(transaction, &selected, &public_key, address_type, path)
+            .await?;
+        let txid = self.send_signed_transaction(&signed.transaction).await?;
+
+        Ok(SentTransaction {
+            txid,
+            near_tx_hashes: signed.near_tx_hashes,
+        })
+    }
+}
+
+impl SignConfigBuilder for BTC {
+    fn sign_config_mut(&mut self) -> &mut SignConfig {
+        &mut self.sign_config
+    }
+}
+
+/// Converts an MPC signature into a low-S, `SIGHASH_ALL` Bitcoin ECDSA signature.
+pub fn to_bitcoin_signature(signature: &SignatureResponse) -> Result<ecdsa::Signature> {
+    let signature =
+        secp256k1::ecdsa::Signature::from_compact(&signature.normalize_s().to_compact()?)
+            .map_err(|e| ChainSignatureError::Signature(e.to_string()))?;
+
+    Ok(ecdsa::Signature {
+        signature,
+        sighash_type: EcdsaSighashType::All,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock_signer::{esplora::FakeEsplora, server::FakeNearRpc, MockSigner};
+    use near_crypto::{InMemorySigner, KeyType};
+    use near_sdk::AccountId;
+    use serde_json::json;
+    use utils::kdf::{derive_child_public_key, naj_pk_to_verifying_key};
+    use utils::types::NearNetwork;
+
+    const ADDRESS: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+    const TXID: &str = "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16";
+
+    /// An Esplora API with two UTXOs of [`ADDRESS`], one of them unconfirmed, that accepts
+    /// broadcasts as [`TXID`].
+    async fn mock_esplora() -> FakeEsplora {
+        let esplora = FakeEsplora::start().await;
+        esplora.set_utxos(
+            ADDRESS,
+            json!([
+                {
+                    "txid": TXID,
+                    "vout": 0,
+                    "value": 150000,
+                    "status": {"confirmed": true, "block_height": 101},
+                },
+                {"txid": TXID, "vout": 1, "value": 90000, "status": {"confirmed": false}},
+            ]),
+        );
+        esplora.set_fee_estimates(json!({"1": 20.4, "6": 4.2, "144": 1.0}));
+        esplora.set_txid(TXID);
+
+        esplora
+    }
+
+    fn btc(esplora_url: &str) -> BTC {
+        let account_id: AccountId = "alice.test.near".parse().unwrap();
+
+        BTC::new(
+            esplora_url,
+            Network::Regtest,
+            NearAuthentication::new(
+                NearNetwork::Testnet,
+                InMemorySigner::from_seed(account_id, KeyType::ED25519, "test"),
+            ),
+            "v1.signer-prod.testnet".parse().unwrap(),
+            KeyVersion::V0,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_esplora_queries() {
+        let esplora = mock_esplora().await;
+        let btc = btc(esplora.url());
+        let address = Address::from_str(ADDRESS)
+            .unwrap()
+            .require_network(Network::Regtest)
+            .unwrap();
+
+        let utxos = btc.get_utxos(&address).await.unwrap();
+        assert_eq!(utxos.len(), 2);
+        assert_eq!(btc.get_balance(&address).await.unwrap(), 240_000);
+        assert_eq!(btc.get_fee_rate().await.unwrap(), 5);
+
+        let (transaction, selected) = BTC::build_transaction(
+            &utxos,
+            &address,
+            100_000,
+            &address,
+            5,
+            BitcoinAddressType::P2WPKH,
+        )
+        .unwrap();
+
+        assert_eq!(selected.len(), 1);
+        assert_eq!(transaction.output.len(), 2);
+        assert_eq!(
+            transaction
+                .output
+                .iter()
+                .map(|o| o.value.to_sat())
+                .sum::<u64>(),
+            150_000 - 5 * (11 + 68 + 68)
+        );
+
+        let txid = btc.send_signed_transaction(&transaction).await.unwrap();
+        assert_eq!(txid.to_string(), TXID);
+        assert_eq!(esplora.broadcasts(), vec![serialize_hex(&transaction)]);
+    }
+
+    #[test]
+    fn test_derived_addresses_match_bitcoin_encoding() {
+        let root_public_key = naj_pk_to_verifying_key("secp256k1:54hU5wcCmVUPFWLDALXMh1fFToZsVXrx9BbTbHzSfQq1Kd1rJZi52iPa4QQxo6s5TgjWqgpY8HamYuUDzG6fAaUq").unwrap();
+        let child_public_key = derive_child_public_key(
+            &root_public_key,
+            "alice.test.near".to_string(),
+            "bitcoin-1".parse().unwrap(),
+        )
+        .unwrap();
+        let public_key =
+            CompressedPublicKey::from_slice(child_public_key.to_encoded_point(true).as_bytes())
+                .unwrap();
+        let secp = secp256k1::Secp256k1::verification_only();
+
+        for network in [
+            Network::Bitcoin,
+            Network::Testnet,
+            Network::Signet,
+            Network::Regtest,
+        ] {
+            let expected = [
+                (
+                    BitcoinAddressType::P2PKH,
+                    Address::p2pkh(public_key, network),
+                ),
+                (
+                    BitcoinAddressType::P2SHP2WPKH,
+                    Address::p2shwpkh(&public_key, network),
+                ),
+                (
+                    BitcoinAddressType::P2WPKH,
+                    Address::p2wpkh(&public_key, network),
+                ),
+                (
+                    BitcoinAddressType::P2TR,
+                    Address::p2tr(&secp, public_key.0.x_only_public_key().0, None, network),
+                ),
+            ];
+
+            for (address_type, address) in expected {
+                let derived = btc_address_from_public_key(
+                    &child_public_key,
+                    address_type,
+                    bitcoin_network(network),
+                )
+                .unwrap();
+                assert_eq!(
+                    derived,
+                    address.to_string(),
+                    "{:?} on {}",
+                    address_type,
+                    network
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_derive_address_with_pinned_root_key() {
+        let root_public_key = naj_pk_to_verifying_key("secp256k1:54hU5wcCmVUPFWLDALXMh1fFToZsVXrx9BbTbHzSfQq1Kd1rJZi52iPa4QQxo6s5TgjWqgpY8HamYuUDzG6fAaUq").unwrap();
+        let btc =
+            btc(mock_esplora().await.url()).with_root_public_key(KeyVersion::V0, root_public_key);
+
+        let child_public_key = derive_child_public_key(
+            &root_public_key,
+            "alice.test.near".to_string(),
+            "bitcoin-1".parse().unwrap(),
+        )
+        .unwrap();
+        let expected = btc_address_from_public_key(
+            &child_public_key,
+            BitcoinAddressType::P2WPKH,
+            BitcoinNetwork::Regtest,
+        )
+        .unwrap();
+
+        let address = btc
+            .derive_address(
+                "alice.test.near",
+                &"bitcoin-1".parse().unwrap(),
+                BitcoinAddressType::P2WPKH,
+            )
+            .await
+            .unwrap();
+        assert_eq!(address.to_string(), expected);
+
+        let mut btc = btc.with_root_public_key(KeyVersion(1), root_public_key);
+        btc.sign_config.key_version = KeyVersion(1);
+        assert!(matches!(
+            btc.derive_address(
+                "alice.test.near",
+                &"bitcoin-1".parse().unwrap(),
+                BitcoinAddressType::P2WPKH
+            )
+            .await,
+            Err(ChainSignatureError::KeyDerivation(
+                KdfError::UnsupportedKeyVersion(1)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_build_transaction_insufficient_funds() {
+        let address = Address::from_str(ADDRESS)
+            .unwrap()
+            .require_network(Network::Regtest)
+            .unwrap();
+        let utxos = vec![Utxo {
+            txid: TXID.parse().unwrap(),
+            vout: 0,
+            value: 10_000,
+            status: UtxoStatus {
+                confirmed: true,
+                block_height: Some(1),
+            },
+        }];
+
+        let result = BTC::build_transaction(
+            &utxos,
+            &address,
+            10_000,
+            &address,
+            1,
+            BitcoinAddressType::P2WPKH,
+        );
+        assert!(result.is_err());
+
+        let result = BTC::build_transaction(
+            &utxos,
+            &address,
+            DUST_LIMIT - 1,
+            &address,
+            1,
+            BitcoinAddressType::P2WPKH,
+        );
+        assert!(matches!(result, Err(ChainSignatureError::Bitcoin(_))));
+    }
+
+    /// A client whose signatures come from a [`MockSigner`] behind a fake NEAR RPC.
+    async fn mock_btc(esplora: &FakeEsplora) -> (FakeNearRpc, BTC) {
+        let contract_id: AccountId = "signer.test.near".parse().unwrap();
+        let near_rpc = FakeNearRpc::start(MockSigner::default(), contract_id.clone()).await;
+
+        let btc = BTC::new(
+            esplora.url(),
+            Network::Regtest,
+            NearAuthentication::new(
+                NearNetwork::custom(near_rpc.url()),
+                InMemorySigner::from_seed(
+                    "alice.test.near".parse().unwrap(),
+                    KeyType::ED25519,
+                    "test",
+                ),
+            ),
+            contract_id,
+            KeyVersion::V0,
+        )
+        .unwrap();
+
+        (near_rpc, btc)
+    }
+
+    /// Checks that input `index` of `transaction` spends `prevout` with a valid low-S
+    /// `SIGHASH_ALL` signature by `public_key`, laid out as `address_type` requires.
+    fn verify_input(
+        transaction: &Transaction,
+        index: usize,
+        prevout: &TxOut,
+        public_key: &PublicKey,
+        address_type: BitcoinAddressType,
+    ) {
+        let input = &transaction.input[index];
+        let p2wpkh_script = ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash().unwrap());
+        let mut sighash_cache = SighashCache::new(transaction);
+
+        let (signature, pushed_key, sighash) = match address_type {
+            BitcoinAddressType::P2PKH => {
+                assert!(input.witness.is_empty());
+                let pushes: Vec<_> = input
+                    .script_sig
+                    .instructions()
+                    .map(|instruction| {
+                        instruction
+                            .unwrap()
+                            .push_bytes()
+                            .unwrap()
+                            .as_bytes()
+                            .to_vec()
+                    })
+                    .collect();
+                let [signature, pushed_key] = <[_; 2]>::try_from(pushes).unwrap();
+                let sighash = sighash_cache
+                    .legacy_signature_hash(index, &prevout.script_pubkey, 1)
+                    .unwrap()
+                    .to_byte_array();
+                (signature, pushed_key, sighash)
+            }
+            _ => {
+                if address_type == BitcoinAddressType::P2SHP2WPKH {
+                    // The scriptSig only pushes the P2WPKH redeem script hashed by the output.
+                    let redeem_script = ScriptBuf::builder()
+                        .push_slice(<&PushBytes>::try_from(p2wpkh_script.as_bytes()).unwrap())
+                        .into_script();
+                    assert_eq!(input.script_sig, redeem_script);
+                    assert_eq!(
+                        prevout.script_pubkey,
+                        ScriptBuf::new_p2sh(&p2wpkh_script.script_hash())
+                    );
+                } else {
+                    assert!(input.script_sig.is_empty());
+                    assert_eq!(prevout.script_pubkey, p2wpkh_script);
+                }
+                assert_eq!(input.witness.len(), 2);
+                let sighash = sighash_cache
+                    .p2wpkh_signature_hash(
+                        index,
+                        &p2wpkh_script,
+                        prevout.value,
+                        EcdsaSighashType::All,
+                    )
+                    .unwrap()
+                    .to_byte_array();
+                (
+                    input.witness.nth(0).unwrap().to_vec(),
+                    input.witness.nth(1).unwrap().to_vec(),
+                    sighash,
+                )
+            }
+        };
+
+        assert_eq!(PublicKey::from_slice(&pushed_key).unwrap(), *public_key);
+        if address_type == BitcoinAddressType::P2PKH {
+            assert_eq!(
+                prevout.script_pubkey,
+                ScriptBuf::new_p2pkh(&public_key.pubkey_hash())
+            );
+        }
+
+        let signature = ecdsa::Signature::from_slice(&signature).unwrap();
+        assert_eq!(signature.sighash_type, EcdsaSighashType::All);
+        let mut normalized = signature.signature;
+        normalized.normalize_s();
+        assert_eq!(normalized, signature.signature, "high-S signature");
+        secp256k1::Secp256k1::verification_only()
+            .verify_ecdsa(
+                &secp256k1::Message::from_digest(sighash),
+                &signature.signature,
+                &public_key.inner,
+            )
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_handle_transaction_with_mock_signer() -> Result<()> {
+        let esplora = mock_esplora().await;
+        let (near_rpc, btc) = mock_btc(&esplora).await;
+        let to = Address::from_str(ADDRESS)
+            .unwrap()
+            .require_network(Network::Regtest)
+            .unwrap();
+        let path: DerivationPath = "bitcoin-1".parse().unwrap();
+        let public_key = btc.derive_public_key("alice.test.near", &path).await?;
+
+        for address_type in [
+            BitcoinAddressType::P2PKH,
+            BitcoinAddressType::P2SHP2WPKH,
+            BitcoinAddressType::P2WPKH,
+        ] {
+            let from = btc
+                .derive_address("alice.test.near", &path, address_type)
+                .await?;
+            let utxos = [
+                (TXID, 0, 60_000),
+                (
+                    "a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d",
+                    3,
+                    50_000,
+                ),
+            ];
+            esplora.set_utxos(
+                &from.to_string(),
+                json!(utxos
+                    .iter()
+                    .map(|(txid, vout, value)| json!({
+                        "txid": txid,
+                        "vout": vout,
+                        "value": value,
+                        "status": {"confirmed": true, "block_height": 101},
+                    }))
+                    .collect::<Vec<_>>()),
+            );
+            let sign_calls = near_rpc.function_calls().len();
+
+            let sent = btc
+                .handle_transaction(ADDRESS, 100_000, path.clone(), address_type)
+                .await?;
+            assert_eq!(sent.txid.to_string(), TXID);
+
+            // Both inputs are signed, each by its own NEAR transaction.
+            assert_eq!(near_rpc.function_calls().len(), sign_calls + 2);
+            assert_eq!(sent.near_tx_hashes.len(), 2);
+            assert_ne!(sent.near_tx_hashes[0], sent.near_tx_hashes[1]);
+            for near_tx_hash in &sent.near_tx_hashes {
+                assert!(near_rpc.has_transaction(near_tx_hash));
+            }
+
+            let transaction: Transaction =
+                bitcoin::consensus::encode::deserialize_hex(esplora.broadcasts().last().unwrap())
+                    .unwrap();
+            assert_eq!(transaction.input.len(), 2);
+            assert_eq!(transaction.output[0].script_pubkey, to.script_pubkey());
+            assert_eq!(transaction.output[0].value, Amount::from_sat(100_000));
+            for (index, input) in transaction.input.iter().enumerate() {
+                let (_, _, value) = utxos
+                    .iter()
+                    .find(|(txid, vout, _)| {
+                        input.previous_output == OutPoint::new(txid.parse().unwrap(), *vout)
+                    })
+                    .unwrap();
+                let prevout = TxOut {
+                    value: Amount::from_sat(*value),
+                    script_pubkey: from.script_pubkey(),
+                };
+                verify_input(&transaction, index, &prevout, &public_key, address_type);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sign_transaction_rejects_unsignable_inputs() -> Result<()> {
+        let esplora = mock_esplora().await;
+        let (near_rpc, btc) = mock_btc(&esplora).await;
+        let address = Address::from_str(ADDRESS)
+            .unwrap()
+            .require_network(Network::Regtest)
+            .unwrap();
+        let path: DerivationPath = "bitcoin-1".parse().unwrap();
+        let public_key = btc.derive_public_key("alice.test.near", &path).await?;
+        let utxos = btc.get_utxos(&address).await?;
+        let (transaction, selected) = BTC::build_transaction(
+            &utxos,
+            &address,
+            100_000,
+            &address,
+            5,
+            BitcoinAddressType::P2WPKH,
+        )?;
+
+        let result = btc
+            .sign_transaction(
+                transaction.clone(),
+                &selected,
+                &public_key,
+                BitcoinAddressType::P2TR,
+                path.clone(),
+            )
+            .await;
+        assert!(matches!(result, Err(ChainSignatureError::Bitcoin(_))));
+
+        let result = btc
+            .sign_transaction(
+                transaction,
+                &utxos,
+                &public_key,
+                BitcoinAddressType::P2WPKH,
+                path,
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(ChainSignatureError::Bitcoin(ref message)) if message == "expected one UTXO per input"
+        ));
+
+        // Nothing was sent to the MPC contract.
+        assert!(near_rpc.function_calls().is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,223 @@
+use std::time::Duration;
+
+use ethers_core::types::{Bytes, TransactionReceipt, H160, H256, U256};
+use near_jsonrpc_client::errors::JsonRpcError;
+use near_jsonrpc_client::methods::broadcast_tx_async::RpcBroadcastTxAsyncError;
+use near_jsonrpc_primitives::types::blocks::RpcBlockError;
+use near_jsonrpc_primitives::types::query::RpcQueryError;
+use near_jsonrpc_primitives::types::transactions::RpcTransactionError;
+use near_primitives::errors::{
+    ActionErrorKind, FunctionCallError, InvalidTxError, TxExecutionError,
+};
+use thiserror::Error;
+use utils::error::{KdfError, SignatureError, SignerError};
+
+pub type Result<T> = std::result::Result<T, ChainSignatureError>;
+
+/// Errors returned by the chain signature clients.
+///
+/// The error is `Send + Sync` so the client futures can be spawned on a multi-threaded runtime.
+#[derive(Debug, Error)]
+pub enum ChainSignatureError {
+    /// A NEAR `query` request failed (transport, server or handler error), e.g. because the
+    /// access key or the contract doesn't exist.
+    #[error("NEAR query failed: {0}")]
+    NearQuery(#[source] Box<JsonRpcError<RpcQueryError>>),
+    /// A `send_tx` or `tx` request failed, other than with an invalid transaction, which is
+    /// reported as [`ChainSignatureError::InvalidNonce`] or
+    /// [`ChainSignatureError::ExecutionFailure`].
+    #[error("NEAR transaction request failed: {0}")]
+    NearTransaction(#[source] Box<JsonRpcError<RpcTransactionError>>),
+    #[error("NEAR transaction broadcast failed: {0}")]
+    NearBroadcast(#[source] Box<JsonRpcError<RpcBroadcastTxAsyncError>>),
+    #[error("NEAR block request failed: {0}")]
+    NearBlock(#[source] Box<JsonRpcError<RpcBlockError>>),
+    /// A `NearNetwork::Custom` endpoint, header or API key is invalid.
+    #[error("invalid NEAR network configuration: {0}")]
+    InvalidNetworkConfig(String),
+    /// The NEAR RPC returned a response of an unexpected kind or shape.
+    #[error("unexpected NEAR RPC response: {0}")]
+    UnexpectedResponse(String),
+    /// The signer contract panicked; holds the panic message reported by the runtime.
+    #[error("contract panicked: {0}")]
+    ContractPanic(String),
+    /// The transaction failed for a reason other than a contract panic.
+    #[error("transaction execution failed: {0}")]
+    ExecutionFailure(String),
+    /// The transaction nonce was not above the access key nonce, usually because another
+    /// transaction used the same access key.
+    #[error("invalid nonce {tx_nonce}, access key nonce is {ak_nonce}")]
+    InvalidNonce { tx_nonce: u64, ak_nonce: u64 },
+    /// The signer contract asks for a larger deposit than the caller allows.
+    #[error("signature deposit of {required} yoctoNEAR exceeds the limit of {max} yoctoNEAR")]
+    DepositTooHigh { required: u128, max: u128 },
+    #[error("failed to sign the NEAR transaction: {0}")]
+    Signer(#[from] SignerError),
+    #[error("timed out after {0:?} waiting for the transaction")]
+    Timeout(Duration),
+    #[error("EVM provider error: {0}")]
+    EvmProvider(#[from] ethers_providers::ProviderError),
+    #[error("invalid EVM transaction: {0}")]
+    EvmTransaction(String),
+    /// The EVM transaction was mined but reverted. `reason` is the decoded revert data, when
+    /// replaying the transaction reproduces it.
+    #[error(
+        "EVM transaction {tx_hash:?} reverted: {}",
+        .reason.as_deref().unwrap_or("no reason given")
+    )]
+    EvmReverted {
+        tx_hash: H256,
+        reason: Option<String>,
+        receipt: Box<TransactionReceipt>,
+    },
+    /// An `eth_call` reverted. `reason` is the decoded revert data, if there is any.
+    #[error(
+        "EVM call reverted: {}",
+        .reason.as_deref().unwrap_or("no reason given")
+    )]
+    EvmCallReverted { reason: Option<String>, data: Bytes },
+    /// Another transaction with the same sender and nonce was mined instead, e.g. a speed-up.
+    #[error("EVM transaction {tx_hash:?} was replaced by another transaction with nonce {nonce}")]
+    EvmTransactionReplaced { tx_hash: H256, nonce: U256 },
+    /// The node rejected the EVM transaction because the sender already used its nonce.
+    #[error("EVM nonce {nonce} of {address:?} was already used")]
+    EvmNonceTooLow { address: H160, nonce: U256 },
+    /// The node no longer knows about the transaction and its nonce is still unused.
+    #[error("EVM transaction {0:?} was dropped from the mempool")]
+    EvmTransactionDropped(H256),
+    #[error("invalid signature: {0}")]
+    Signature(String),
+    /// The MPC signature is malformed, malleable or was not made by the expected key.
+    #[error("signature verification failed: {0}")]
+    SignatureVerification(#[from] SignatureError),
+    #[error("key derivation failed: {0}")]
+    KeyDerivation(#[from] KdfError),
+    #[error("Bitcoin error: {0}")]
+    Bitcoin(String),
+    #[error("insufficient funds: have {available} sats, need {required} sats")]
+    InsufficientFunds { available: u64, required: u64 },
+    #[error("Esplora API error: {0}")]
+    Esplora(#[from] reqwest::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// The NEAR RPC errors are boxed to keep [`Result`] small.
+macro_rules! impl_from_json_rpc_error {
+    ($($handler_error:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<JsonRpcError<$handler_error>> for ChainSignatureError {
+                fn from(err: JsonRpcError<$handler_error>) -> Self {
+                    ChainSignatureError::$variant(Box::new(err))
+                }
+            }
+        )*
+    };
+}
+
+impl_from_json_rpc_error! {
+    RpcQueryError => NearQuery,
+    RpcTransactionError => NearTransaction,
+    RpcBroadcastTxAsyncError => NearBroadcast,
+    RpcBlockError => NearBlock,
+}
+
+impl From<TxExecutionError> for ChainSignatureError {
+    fn from(err: TxExecutionError) -> Self {
+        match err {
+            TxExecutionError::ActionError(action_error) => match action_error.kind {
+                ActionErrorKind::FunctionCallError(FunctionCallError::ExecutionError(message)) => {
+                    ChainSignatureError::ContractPanic(message)
+                }
+                kind => ChainSignatureError::ExecutionFailure(kind.to_string()),
+            },
+            TxExecutionError::InvalidTxError(err) => err.into(),
+        }
+    }
+}
+
+impl From<InvalidTxError> for ChainSignatureError {
+    fn from(err: InvalidTxError) -> Self {
+        match err {
+            InvalidTxError::InvalidNonce { tx_nonce, ak_nonce } => {
+                ChainSignatureError::InvalidNonce { tx_nonce, ak_nonce }
+            }
+            err => ChainSignatureError::ExecutionFailure(err.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{NearRpcClient, WaitPolicy};
+    use crate::nonce::NonceManager;
+    use crate::rpc::{call_sign, SignFee};
+    use near_crypto::{InMemorySigner, KeyType};
+    use near_jsonrpc_client::JsonRpcClient;
+    use near_primitives::errors::ActionError;
+    use utils::types::SignRequest;
+
+    fn assert_send_sync<T: Send + Sync + 'static>() {}
+
+    fn assert_send<T: Send>(_: &T) {}
+
+    #[test]
+    fn test_error_is_send_sync() {
+        assert_send_sync::<ChainSignatureError>();
+
+        let client = NearRpcClient::from(JsonRpcClient::connect("http://localhost:3030"));
+        let signer =
+            InMemorySigner::from_seed("alice.test.near".parse().unwrap(), KeyType::ED25519, "test");
+        let nonces = NonceManager::new();
+        let wait_policy = WaitPolicy::default();
+        let fee = SignFee::default();
+        let future = call_sign(
+            &client,
+            "signer.test.near".parse().unwrap(),
+            SignRequest {
+                payload: [0; 32],
+                path: "test".parse().unwrap(),
+                key_version: 0,
+            },
+            &signer,
+            &nonces,
+            &wait_policy,
+            &fee,
+        );
+        assert_send(&future);
+    }
+
+    #[test]
+    fn test_near_rpc_errors_keep_their_handler_error() {
+        use near_jsonrpc_client::errors::JsonRpcServerError;
+
+        let err: ChainSignatureError = JsonRpcError::ServerError(JsonRpcServerError::HandlerError(
+            RpcTransactionError::TimeoutError,
+        ))
+        .into();
+
+        assert!(matches!(
+            err,
+            ChainSignatureError::NearTransaction(ref err)
+                if matches!(err.handler_error(), Some(RpcTransactionError::TimeoutError))
+        ));
+    }
+
+    #[test]
+    fn test_contract_panic_is_extracted() {
+        let err: ChainSignatureError = TxExecutionError::ActionError(ActionError {
+            index: Some(0),
+            kind: ActionErrorKind::FunctionCallError(FunctionCallError::ExecutionError(
+                "Smart contract panicked: Signature request has timed out.".to_string(),
+            )),
+        })
+        .into();
+
+        assert!(matches!(
+            err,
+            ChainSignatureError::ContractPanic(ref message)
+                if message == "Smart contract panicked: Signature request has timed out."
+        ));
+    }
+}
@@ -1,28 +1,94 @@
-use ethers_core::{
-    k256::elliptic_curve::point::AffineCoordinates,
-    types::{
-        transaction::{eip2718::TypedTransaction, eip2930::AccessList},
-        BlockNumber, Eip1559TransactionRequest, H160, H256, U256,
-    },
+use ethers_core::abi::{decode, encode, short_signature, ParamType, Token};
+use ethers_core::types::{
+    transaction::{eip2718::TypedTransaction, eip2930::AccessList},
+    BlockNumber, Bytes, Eip1559TransactionRequest, Eip2930TransactionRequest, Signature,
+    TransactionReceipt, TransactionRequest, H160, H256, U256,
 };
-use ethers_providers::{JsonRpcClient, Middleware, Provider};
-use near_jsonrpc_client::JsonRpcClient as NearJsonRpcClient;
+use ethers_core::utils::{get_contract_address, get_create2_address, public_key_to_address};
+use ethers_providers::{JsonRpcClient, Middleware, Provider, ProviderError, RpcError};
+use k256::ecdsa::VerifyingKey;
+use near_primitives::hash::CryptoHash;
 use near_sdk::AccountId;
 use utils::{
-    kdf::derive_eth_address,
-    types::{NearAuthentication, SignRequest},
+    kdf::eth_address_from_public_key,
+    types::{DerivationPath, KeyVersion, NearAuthentication},
 };
 
+pub mod contract;
+pub mod erc20;
+pub mod nft;
+
 use crate::{
-    api::get_near_client,
-    rpc::{call_public_key, call_sign},
+    error::{ChainSignatureError, Result},
+    fee::{replacement_fee, FeeStrategy},
+    nonce::EvmNonceManager,
+    receipt::{decode_revert_reason, revert_data, wait_for_receipt, ConfirmationPolicy},
+    rpc::MAX_NONCE_RETRIES,
+    sign::{SignConfig, SignConfigBuilder},
 };
 
+/// The deterministic deployment proxy, deployed at the same address on most EVM chains. It
+/// creates the contract with CREATE2 from calldata made of a 32-byte salt and the init code.
+pub const DETERMINISTIC_DEPLOYER: H160 = H160([
+    0x4e, 0x59, 0xb4, 0x48, 0x47, 0xb3, 0x79, 0x57, 0x85, 0x88, 0x92, 0x0c, 0xa7, 0x8f, 0xbf, 0x26,
+    0xc0, 0xb4, 0x95, 0x6c,
+]);
+
+/// How [`EVM::deploy_contract`] creates a contract.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Deployment {
+    /// A contract creation transaction. The address depends on the sender and its nonce.
+    Create,
+    /// A call to a CREATE2 deployer such as [`DETERMINISTIC_DEPLOYER`]. The address depends on
+    /// the deployer, the salt and the init code only.
+    Create2 { deployer: H160, salt: H256 },
+}
+
+/// The type of the transactions built by the contract helpers, such as
+/// [`EVM::erc20_transfer`] and [`EVM::call_contract`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TransactionType {
+    /// Pre-EIP-2718 transactions priced with a gas price, for chains without EIP-1559.
+    Legacy,
+    /// EIP-2930 transactions, priced with a gas price and carrying an empty access list.
+    Eip2930,
+    #[default]
+    Eip1559,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeployedContract {
+    pub address: H160,
+    pub receipt: TransactionReceipt,
+    /// The NEAR transaction that requested the signature of the deployment.
+    pub near_tx_hash: CryptoHash,
+}
+
+/// A transaction completed and signed by [`EVM::sign_transaction`], ready for
+/// [`EVM::send_signed_transaction`].
+#[derive(Debug, Clone)]
+pub struct SignedTransaction {
+    pub transaction: TypedTransaction,
+    pub signature: Signature,
+    /// The NEAR transaction that requested the signature.
+    pub near_tx_hash: CryptoHash,
+}
+
+/// A transaction sent by [`EVM::handle_transaction`] and the helpers built on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SentTransaction {
+    pub tx_hash: H256,
+    /// The NEAR transaction that requested the signature.
+    pub near_tx_hash: CryptoHash,
+}
+
 pub struct EVM<P: JsonRpcClient> {
     evm_provider: Provider<P>,
-    near_authentication: NearAuthentication,
-    contract: AccountId,
-    near_client: NearJsonRpcClient,
+    sign_config: SignConfig,
+    evm_nonce_manager: EvmNonceManager,
+    fee_strategy: Option<FeeStrategy>,
+    transaction_type: TransactionType,
+    confirmation_policy: ConfirmationPolicy,
 }
 
 impl<P: JsonRpcClient> EVM<P> {
@@ -30,134 +96,1012 @@ impl<P: JsonRpcClient> EVM<P> {
         evm_provider: Provider<P>,
         near_authentication: NearAuthentication,
         contract: AccountId,
-    ) -> Self {
-        Self {
+        key_version: KeyVersion,
+    ) -> Result<Self> {
+        Ok(Self {
             evm_provider,
-            near_authentication: near_authentication.clone(),
-            contract,
-            near_client: get_near_client(near_authentication.network),
-        }
+            sign_config: SignConfig::new(near_authentication, contract, key_version)?,
+            evm_nonce_manager: EvmNonceManager::new(),
+            fee_strategy: None,
+            transaction_type: TransactionType::default(),
+            confirmation_policy: ConfirmationPolicy::default(),
+        })
+    }
+
+    /// Shares nonce tracking of derived addresses with other clients sending from them.
+    pub fn with_evm_nonce_manager(mut self, evm_nonce_manager: EvmNonceManager) -> Self {
+        self.evm_nonce_manager = evm_nonce_manager;
+        self
+    }
+
+    /// Sets how transactions are priced, instead of the default strategy for the chain from
+    /// [`FeeStrategy::for_chain`].
+    pub fn with_fee_strategy(mut self, fee_strategy: FeeStrategy) -> Self {
+        self.fee_strategy = Some(fee_strategy);
+        self
+    }
+
+    /// Sets the type of the transactions built by the contract helpers. Transactions passed in by
+    /// the caller keep their own type.
+    pub fn with_transaction_type(mut self, transaction_type: TransactionType) -> Self {
+        self.transaction_type = transaction_type;
+        self
+    }
+
+    /// Sets how many confirmations to wait for, and for how long, before returning receipts.
+    pub fn with_confirmation_policy(mut self, confirmation_policy: ConfirmationPolicy) -> Self {
+        self.confirmation_policy = confirmation_policy;
+        self
     }
 
     pub async fn send_signed_transaction(
         &self,
         transaction: TypedTransaction,
-        signature: ethers_core::types::Signature,
-    ) -> Result<H256, Box<dyn std::error::Error>> {
+        signature: Signature,
+    ) -> Result<H256> {
         let signed_tx = transaction.rlp_signed(&signature);
 
         match self.evm_provider.send_raw_transaction(signed_tx).await {
             Ok(tx_hash) => Ok(tx_hash.tx_hash()),
-            Err(e) => {
-                eprintln!("Error sending transaction: {:?}", e);
-                Err(Box::new(e))
-            }
+            Err(e) if is_nonce_too_low(&e) => Err(ChainSignatureError::EvmNonceTooLow {
+                address: transaction.from().copied().unwrap_or_default(),
+                nonce: transaction.nonce().copied().unwrap_or_default(),
+            }),
+            Err(e) => Err(e.into()),
         }
     }
 
-    pub async fn get_fee_properties(&self) -> Result<(U256, U256), Box<dyn std::error::Error>> {
-        let latest_block = self
-            .evm_provider
-            .get_block(BlockNumber::Latest)
-            .await?
-            .ok_or("Latest block not found")?;
+    /// The address derived for `path` from the authenticated NEAR account.
+    async fn own_address(&self, path: &DerivationPath) -> Result<H160> {
+        let public_key = self
+            .derive_public_key(self.sign_config.account_id().as_str(), path)
+            .await?;
+
+        Ok(public_key_to_address(&public_key))
+    }
+
+    /// Runs `data` on the contract `to` with `eth_call` at the latest block and returns the
+    /// output.
+    async fn call(&self, to: H160, data: Vec<u8>) -> Result<Bytes> {
+        self.eth_call(&self.contract_transaction(to, data)).await
+    }
+
+    /// A call of the contract `contract` with `data`, of the configured transaction type.
+    fn contract_transaction(&self, contract: H160, data: Vec<u8>) -> TypedTransaction {
+        match self.transaction_type {
+            TransactionType::Legacy => TransactionRequest::new().to(contract).data(data).into(),
+            TransactionType::Eip2930 => Eip2930TransactionRequest::new(
+                TransactionRequest::new().to(contract).data(data),
+                AccessList::default(),
+            )
+            .into(),
+            TransactionType::Eip1559 => Eip1559TransactionRequest::new()
+                .to(contract)
+                .data(data)
+                .into(),
+        }
+    }
+
+    /// Runs `transaction` with `eth_call` at the latest block and returns the output. Fails with
+    /// [`ChainSignatureError::EvmCallReverted`] if it reverts.
+    async fn eth_call(&self, transaction: &TypedTransaction) -> Result<Bytes> {
+        self.evm_provider
+            .call(transaction, None)
+            .await
+            .map_err(|err| match revert_data(&err) {
+                Some(data) => ChainSignatureError::EvmCallReverted {
+                    reason: decode_revert_reason(&data),
+                    data: data.into(),
+                },
+                None => err.into(),
+            })
+    }
 
-        let base_fee_per_gas = latest_block.base_fee_per_gas.unwrap_or_default();
-        let max_priority_fee_per_gas = U256::from(1_000_000_000);
-        let max_fee_per_gas = base_fee_per_gas + max_priority_fee_per_gas;
+    /// Returns `(max_fee_per_gas, max_priority_fee_per_gas)` according to the fee strategy.
+    pub async fn get_fee_properties(&self) -> Result<(U256, U256)> {
+        let fee_strategy = match self.fee_strategy {
+            Some(fee_strategy) => fee_strategy,
+            None => FeeStrategy::for_chain(self.evm_provider.get_chainid().await?.as_u64()),
+        };
 
-        Ok((max_fee_per_gas, max_priority_fee_per_gas))
+        fee_strategy.estimate(&self.evm_provider).await
     }
 
+    /// The gas price of legacy and EIP-2930 transactions: `max_fee_per_gas` of a fixed fee
+    /// strategy, or `eth_gasPrice` otherwise.
+    async fn get_gas_price(&self) -> Result<U256> {
+        match self.fee_strategy {
+            Some(FeeStrategy::Fixed {
+                max_fee_per_gas, ..
+            }) => Ok(max_fee_per_gas),
+            _ => Ok(self.evm_provider.get_gas_price().await?),
+        }
+    }
+
+    /// Fills in the fields the caller left unset: the sender, chain id, nonce, fees and gas
+    /// limit. The transaction type and every field that is already set, such as an access list
+    /// or gas limit, are kept. Legacy and EIP-2930 transactions are priced with `eth_gasPrice`,
+    /// for chains without EIP-1559.
+    ///
+    /// A missing nonce is set to the sender's `pending` transaction count. Nothing is reserved,
+    /// so transactions filled in concurrently get the same nonce; only
+    /// [`Self::handle_transaction`] and the helpers built on it reserve nonces.
     pub async fn attach_gas_and_nonce(
         &self,
         transaction: &TypedTransaction,
         from: &str,
-    ) -> Result<TypedTransaction, Box<dyn std::error::Error>> {
-        let (max_fee_per_gas, max_priority_fee_per_gas) = self.get_fee_properties().await?;
-        let nonce = self.evm_provider.get_transaction_count(from, None).await?;
-        let gas_estimate = self
-            .evm_provider
-            .estimate_gas(&transaction.clone(), None)
-            .await?;
+    ) -> Result<TypedTransaction> {
+        let from = from
+            .parse::<H160>()
+            .map_err(|e| ChainSignatureError::EvmTransaction(format!("invalid sender: {}", e)))?;
+        let mut transaction = transaction.clone();
+        transaction.set_from(from);
 
-        Ok(TypedTransaction::Eip1559(
-            Eip1559TransactionRequest::new()
-                .from(from.parse::<H160>().unwrap())
-                .to(transaction.to().cloned().unwrap())
-                .gas(gas_estimate)
-                .value(transaction.value().cloned().unwrap_or_default())
-                .data(transaction.data().cloned().unwrap_or_default())
-                .nonce(nonce)
-                .access_list(AccessList::default())
-                .max_priority_fee_per_gas(max_priority_fee_per_gas)
-                .max_fee_per_gas(max_fee_per_gas)
-                .chain_id(self.evm_provider.get_chainid().await?.as_u64()),
-        ))
-    }
-
-    pub async fn get_balance(&self, address: &str) -> Result<String, Box<dyn std::error::Error>> {
+        if transaction.chain_id().is_none() {
+            transaction.set_chain_id(self.evm_provider.get_chainid().await?.as_u64());
+        }
+
+        match &mut transaction {
+            TypedTransaction::Eip1559(request) => {
+                if request.max_fee_per_gas.is_none() || request.max_priority_fee_per_gas.is_none() {
+                    let (max_fee_per_gas, max_priority_fee_per_gas) =
+                        self.get_fee_properties().await?;
+                    // A priority fee set by the caller replaces the estimated one on top of the
+                    // base fee headroom.
+                    let priority_fee = *request
+                        .max_priority_fee_per_gas
+                        .get_or_insert(max_priority_fee_per_gas);
+                    request.max_fee_per_gas.get_or_insert(
+                        max_fee_per_gas.saturating_sub(max_priority_fee_per_gas) + priority_fee,
+                    );
+                }
+            }
+            TypedTransaction::Legacy(_) | TypedTransaction::Eip2930(_) => {
+                if transaction.gas_price().is_none() {
+                    transaction.set_gas_price(self.get_gas_price().await?);
+                }
+            }
+        }
+
+        if transaction.gas().is_none() {
+            let gas_estimate = self.evm_provider.estimate_gas(&transaction, None).await?;
+            transaction.set_gas(gas_estimate);
+        }
+
+        if transaction.nonce().is_none() {
+            let nonce = self
+                .evm_provider
+                .get_transaction_count(from, Some(BlockNumber::Pending.into()))
+                .await?;
+            transaction.set_nonce(nonce);
+        }
+
+        Ok(transaction)
+    }
+
+    pub async fn get_balance(&self, address: &str) -> Result<String> {
         let balance = self.evm_provider.get_balance(address, None).await?;
         Ok(ethers_core::utils::format_ether(balance))
     }
 
-    pub async fn derive_address(
+    pub async fn derive_public_key(
         &self,
         signer_id: &str,
-        path: &str,
-    ) -> Result<String, Box<dyn std::error::Error>> {
-        let naj_public_key = call_public_key(&self.near_client, self.contract.clone()).await?;
-        let eth_address =
-            derive_eth_address(&naj_public_key, signer_id.to_string(), path.to_string()).await?;
-        Ok(eth_address)
+        path: &DerivationPath,
+    ) -> Result<VerifyingKey> {
+        self.sign_config.derive_public_key(signer_id, path).await
     }
 
-    pub async fn handle_transaction(
+    pub async fn derive_address(&self, signer_id: &str, path: &DerivationPath) -> Result<String> {
+        let public_key = self.derive_public_key(signer_id, path).await?;
+        Ok(eth_address_from_public_key(&public_key))
+    }
+
+    /// Fills in `transaction` with [`Self::attach_gas_and_nonce`] and has it signed by the MPC
+    /// contract with the key derived for `path`.
+    pub async fn sign_transaction(
         &self,
-        data: TypedTransaction,
-        path: String,
-    ) -> Result<H256, Box<dyn std::error::Error>> {
-        let from = self
-            .derive_address(&self.near_authentication.account_id.to_string(), &path)
+        transaction: TypedTransaction,
+        path: DerivationPath,
+    ) -> Result<SignedTransaction> {
+        let public_key = self
+            .derive_public_key(self.sign_config.account_id().as_str(), &path)
             .await?;
-        let transaction = self.attach_gas_and_nonce(&data, &from).await?;
+        let from = eth_address_from_public_key(&public_key);
+        let transaction = self.attach_gas_and_nonce(&transaction, &from).await?;
+        let payload: [u8; 32] = transaction.sighash().into();
+
+        let outcome = self.sign_config.sign(payload, path).await?;
+        let signature = outcome.signature.normalize_s();
+
+        // A signature from the wrong key would otherwise be broadcast and attributed to whatever
+        // address it happens to recover to.
+        signature.verify(&public_key, &payload)?;
+
+        let signature =
+            signature.to_ethers_signature(transaction.chain_id().map(|id| id.as_u64()))?;
+
+        Ok(SignedTransaction {
+            transaction,
+            signature,
+            near_tx_hash: outcome.tx_hash,
+        })
+    }
+
+    /// Signs and sends `data`. If its nonce was left to this client, one is reserved from the
+    /// EVM nonce manager, so transactions from the same address that are still pending or being
+    /// signed get consecutive nonces. If the node reports the nonce as used, the transaction is
+    /// signed anew with another one, up to `MAX_NONCE_RETRIES` times.
+    async fn sign_and_send(
+        &self,
+        data: TypedTransaction,
+        path: DerivationPath,
+    ) -> Result<(TypedTransaction, SentTransaction)> {
+        if data.nonce().is_some() {
+            let signed = self.sign_transaction(data, path).await?;
+            let tx_hash = self
+                .send_signed_transaction(signed.transaction.clone(), signed.signature)
+                .await?;
 
-        let sign_request = SignRequest {
-            payload: transaction.sighash().into(),
-            path,
-            key_version: 0,
+            return Ok((
+                signed.transaction,
+                SentTransaction {
+                    tx_hash,
+                    near_tx_hash: signed.near_tx_hash,
+                },
+            ));
+        }
+
+        let from = self.own_address(&path).await?;
+        let chain_id = match data.chain_id() {
+            Some(chain_id) => chain_id.as_u64(),
+            None => self.evm_provider.get_chainid().await?.as_u64(),
         };
+        let mut retries = 0;
+
+        loop {
+            let nonce = self
+                .evm_nonce_manager
+                .reserve(&self.evm_provider, chain_id, from)
+                .await?;
+            let mut transaction = data.clone();
+            transaction.set_chain_id(chain_id).set_nonce(nonce);
 
-        let signature = call_sign(
-            &self.near_client,
-            self.contract.clone(),
-            sign_request,
-            self.near_authentication.key_pair.clone(),
+            let signed = match self.sign_transaction(transaction, path.clone()).await {
+                Ok(signed) => signed,
+                Err(err) => {
+                    self.evm_nonce_manager.release(chain_id, from, nonce).await;
+                    return Err(err);
+                }
+            };
+
+            match self
+                .send_signed_transaction(signed.transaction.clone(), signed.signature)
+                .await
+            {
+                Ok(tx_hash) => {
+                    self.evm_nonce_manager.confirm(chain_id, from, nonce).await;
+                    return Ok((
+                        signed.transaction,
+                        SentTransaction {
+                            tx_hash,
+                            near_tx_hash: signed.near_tx_hash,
+                        },
+                    ));
+                }
+                Err(err) => {
+                    // A failed broadcast may still have reached the network and used the nonce.
+                    self.evm_nonce_manager
+                        .invalidate(chain_id, from, nonce)
+                        .await;
+                    match err {
+                        ChainSignatureError::EvmNonceTooLow { .. }
+                            if retries < MAX_NONCE_RETRIES =>
+                        {
+                            retries += 1;
+                        }
+                        err => return Err(err),
+                    }
+                }
+            }
+        }
+    }
+
+    pub async fn handle_transaction(
+        &self,
+        data: TypedTransaction,
+        path: DerivationPath,
+    ) -> Result<SentTransaction> {
+        let (_, sent) = self.sign_and_send(data, path).await?;
+
+        Ok(sent)
+    }
+
+    /// Like [`Self::handle_transaction`], but waits for the transaction to be confirmed according
+    /// to the confirmation policy and returns its receipt.
+    pub async fn handle_transaction_and_wait(
+        &self,
+        data: TypedTransaction,
+        path: DerivationPath,
+    ) -> Result<TransactionReceipt> {
+        let (transaction, sent) = self.sign_and_send(data, path).await?;
+
+        self.wait_for_receipt(&transaction, sent.tx_hash).await
+    }
+
+    /// Waits for `transaction`, already signed and sent as `tx_hash`, to be confirmed according
+    /// to the confirmation policy. See [`wait_for_receipt`] for the ways it can fail.
+    pub async fn wait_for_receipt(
+        &self,
+        transaction: &TypedTransaction,
+        tx_hash: H256,
+    ) -> Result<TransactionReceipt> {
+        wait_for_receipt(
+            &self.evm_provider,
+            transaction,
+            tx_hash,
+            &self.confirmation_policy,
         )
-        .await?;
+        .await
+    }
+
+    /// Re-sends the pending transaction `tx_hash`, sent from the address derived for `path`, with
+    /// the same nonce and higher fees so that it replaces the original. Returns the hash of the
+    /// replacement.
+    pub async fn speed_up(&self, tx_hash: H256, path: DerivationPath) -> Result<SentTransaction> {
+        let transaction = self.get_pending_transaction(tx_hash, &path).await?;
+
+        self.replace_transaction(transaction, path).await
+    }
+
+    /// Replaces the pending transaction `tx_hash`, sent from the address derived for `path`, with
+    /// an empty transfer to the sender itself, freeing its nonce for later transactions. Returns
+    /// the hash of the replacement.
+    pub async fn cancel(&self, tx_hash: H256, path: DerivationPath) -> Result<SentTransaction> {
+        let mut transaction = self.get_pending_transaction(tx_hash, &path).await?;
+        let from = *transaction
+            .from()
+            .expect("set from the pending transaction");
+        transaction
+            .set_to(from)
+            .set_value(U256::zero())
+            .set_data(Default::default())
+            .set_access_list(Default::default())
+            .set_gas(21_000);
+
+        self.replace_transaction(transaction, path).await
+    }
+
+    async fn get_pending_transaction(
+        &self,
+        tx_hash: H256,
+        path: &DerivationPath,
+    ) -> Result<TypedTransaction> {
+        let transaction = self
+            .evm_provider
+            .get_transaction(tx_hash)
+            .await?
+            .ok_or_else(|| {
+                ChainSignatureError::EvmTransaction(format!("transaction {:?} not found", tx_hash))
+            })?;
 
-        let ethers_signature = ethers_core::types::Signature {
-            r: U256::from_big_endian(&signature.big_r.affine_point.x() as &[u8]),
-            s: U256::from_big_endian(&signature.s.scalar.to_bytes() as &[u8]),
-            v: signature.recovery_id.into(),
+        if transaction.block_number.is_some() {
+            return Err(ChainSignatureError::EvmTransaction(format!(
+                "transaction {:?} is already mined",
+                tx_hash
+            )));
+        }
+
+        let from = self.own_address(path).await?;
+        if from != transaction.from {
+            return Err(ChainSignatureError::EvmTransaction(format!(
+                "transaction {:?} was not sent from {:?}, the address derived for {}",
+                tx_hash, from, path
+            )));
+        }
+
+        Ok((&transaction).into())
+    }
+
+    /// Raises the fees of `transaction` by the replacement minimum, or to the current fees if
+    /// those are higher, then signs and sends it.
+    async fn replace_transaction(
+        &self,
+        mut transaction: TypedTransaction,
+        path: DerivationPath,
+    ) -> Result<SentTransaction> {
+        match &mut transaction {
+            TypedTransaction::Eip1559(request) => {
+                let (max_fee_per_gas, max_priority_fee_per_gas) = self.get_fee_properties().await?;
+                let priority_fee =
+                    replacement_fee(request.max_priority_fee_per_gas.unwrap_or_default())
+                        .max(max_priority_fee_per_gas);
+                let max_fee = replacement_fee(request.max_fee_per_gas.unwrap_or_default())
+                    .max(max_fee_per_gas.saturating_sub(max_priority_fee_per_gas) + priority_fee);
+
+                request.max_priority_fee_per_gas = Some(priority_fee);
+                request.max_fee_per_gas = Some(max_fee);
+            }
+            TypedTransaction::Legacy(_) | TypedTransaction::Eip2930(_) => {
+                let gas_price = replacement_fee(transaction.gas_price().unwrap_or_default())
+                    .max(self.get_gas_price().await?);
+                transaction.set_gas_price(gas_price);
+            }
+        }
+
+        let signed = self.sign_transaction(transaction, path).await?;
+        let tx_hash = self
+            .send_signed_transaction(signed.transaction, signed.signature)
+            .await?;
+
+        Ok(SentTransaction {
+            tx_hash,
+            near_tx_hash: signed.near_tx_hash,
+        })
+    }
+
+    /// Deploys a contract from the address derived for `path` and waits for the receipt
+    /// according to the confirmation policy.
+    ///
+    /// `transaction` holds the init code as its data and no recipient. With
+    /// [`Deployment::Create2`], it is sent to the deployer instead, with the salt prepended to
+    /// the init code, so the address doesn't depend on the sender's nonce.
+    pub async fn deploy_contract(
+        &self,
+        mut transaction: TypedTransaction,
+        deployment: Deployment,
+        path: DerivationPath,
+    ) -> Result<DeployedContract> {
+        if transaction.to().is_some() {
+            return Err(ChainSignatureError::EvmTransaction(
+                "contract creation transactions must not have a recipient".to_string(),
+            ));
+        }
+        let init_code = transaction.data().cloned().unwrap_or_default();
+
+        let create2_address = match deployment {
+            Deployment::Create => None,
+            Deployment::Create2 { deployer, salt } => {
+                transaction.set_to(deployer);
+                transaction.set_data([salt.as_bytes(), &init_code].concat().into());
+                Some(get_create2_address(deployer, salt, &init_code))
+            }
         };
 
-        self.send_signed_transaction(transaction, ethers_signature)
-            .await
+        let (transaction, sent) = self.sign_and_send(transaction, path).await?;
+        let address = match create2_address {
+            Some(address) => address,
+            None => get_contract_address(
+                *transaction.from().expect("set by sign_and_send"),
+                *transaction.nonce().expect("set by sign_and_send"),
+            ),
+        };
+
+        let receipt = self.wait_for_receipt(&transaction, sent.tx_hash).await?;
+
+        Ok(DeployedContract {
+            address,
+            receipt,
+            near_tx_hash: sent.near_tx_hash,
+        })
+    }
+}
+
+impl<P: JsonRpcClient> SignConfigBuilder for EVM<P> {
+    fn sign_config_mut(&mut self) -> &mut SignConfig {
+        &mut self.sign_config
     }
 }
 
+/// ABI-encodes a call of `function` with `arguments` of types `inputs`.
+fn calldata(function: &str, inputs: &[ParamType], arguments: &[Token]) -> Vec<u8> {
+    [&short_signature(function, inputs)[..], &encode(arguments)].concat()
+}
+
+/// Decodes the output of a call that returns a single value of type `kind`.
+fn decode_output(output: &[u8], kind: ParamType) -> Result<Token> {
+    match decode(std::slice::from_ref(&kind), output) {
+        Ok(mut tokens) if tokens.len() == 1 => Ok(tokens.remove(0)),
+        _ => Err(ChainSignatureError::EvmTransaction(format!(
+            "expected a {}, got {}",
+            kind,
+            Bytes::from(output.to_vec())
+        ))),
+    }
+}
+
+/// Whether the node rejected a transaction because its nonce was already used. Clients word
+/// this differently, e.g. geth says "nonce too low" and Nethermind "OldNonce".
+fn is_nonce_too_low(err: &ProviderError) -> bool {
+    let message = match err.as_error_response() {
+        Some(response) => response.message.to_lowercase(),
+        None => err.to_string().to_lowercase(),
+    };
+
+    ["nonce too low", "nonce_too_low", "oldnonce"]
+        .iter()
+        .any(|pattern| message.contains(pattern))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use dotenv::dotenv;
-    use ethers_core::types::U256;
+    use ethers_core::types::transaction::eip2930::{AccessList, AccessListItem};
+    use ethers_core::types::{
+        Block, Bytes, Eip1559TransactionRequest, Eip2930TransactionRequest, Transaction,
+        TransactionRequest, U256,
+    };
+    use ethers_core::utils::rlp::Rlp;
     use ethers_providers::Http;
-    use near_crypto::{InMemorySigner, SecretKey};
+    use mock_signer::{evm::FakeEvmRpc, server::FakeNearRpc, MockSigner};
+    use near_crypto::{InMemorySigner, KeyType, SecretKey};
     use near_primitives::types::AccountId;
+    use serde_json::json;
     use utils::types::NearNetwork;
 
     #[tokio::test]
+    async fn test_handle_transaction_with_mock_signer() -> Result<()> {
+        let (near_rpc, evm_rpc, evm) = mock_evm().await;
+
+        let transaction_request = TypedTransaction::Eip1559(
+            Eip1559TransactionRequest::new()
+                .to("0x4174678c78fEaFd778c1ff319D5D326701449b25"
+                    .parse::<H160>()
+                    .unwrap())
+                .value(U256::from(3500000000000000u64)),
+        );
+
+        let sent = evm
+            .handle_transaction(transaction_request, "eth".parse().unwrap())
+            .await?;
+        assert_eq!(sent.tx_hash, H256::repeat_byte(0xab));
+        assert!(near_rpc.has_transaction(&sent.near_tx_hash));
+
+        assert_eq!(evm_rpc.requests("eth_sendRawTransaction").len(), 1);
+        let (transaction, _) = last_signed_transaction(&evm_rpc, &evm, "eth").await;
+        assert_eq!(transaction.nonce(), Some(&U256::from(5)));
+        assert_eq!(transaction.chain_id(), Some(11155111.into()));
+
+        Ok(())
+    }
+
+    /// An `EVM` client backed by fake NEAR and EVM RPC servers, with default EVM responses for
+    /// the Sepolia chain id, nonce 5, a base fee of 7 wei and a gas price of 3 gwei.
+    pub(super) async fn mock_evm() -> (FakeNearRpc, FakeEvmRpc, EVM<Http>) {
+        let contract_id: AccountId = "signer.test.near".parse().unwrap();
+        let near_rpc = FakeNearRpc::start(MockSigner::default(), contract_id.clone()).await;
+        let evm_rpc = FakeEvmRpc::start().await;
+
+        let latest_block = Block::<H256> {
+            base_fee_per_gas: Some(U256::from(7)),
+            ..Default::default()
+        };
+        evm_rpc.set_response("eth_getBlockByNumber", json!(latest_block));
+        evm_rpc.set_response(
+            "eth_feeHistory",
+            json!({
+                "oldestBlock": "0x1",
+                "baseFeePerGas": ["0x7", "0x7"],
+                "gasUsedRatio": [0.5],
+                "reward": [["0x3b9aca00"]],
+            }),
+        );
+        evm_rpc.set_response("eth_getTransactionCount", json!("0x5"));
+        evm_rpc.set_response("eth_estimateGas", json!("0x5208"));
+        evm_rpc.set_response("eth_gasPrice", json!(U256::from(3_000_000_000u64)));
+        evm_rpc.set_response("eth_chainId", json!("0xaa36a7"));
+        evm_rpc.set_response("eth_sendRawTransaction", json!(H256::repeat_byte(0xab)));
+        evm_rpc.set_response("eth_blockNumber", json!("0x1"));
+
+        let evm = EVM::new(
+            Provider::<Http>::try_from(evm_rpc.url())
+                .unwrap()
+                .interval(std::time::Duration::from_millis(10)),
+            NearAuthentication::new(
+                NearNetwork::custom(near_rpc.url()),
+                InMemorySigner::from_seed(
+                    "alice.test.near".parse().unwrap(),
+                    KeyType::ED25519,
+                    "test",
+                ),
+            ),
+            contract_id,
+            KeyVersion::V0,
+        )
+        .unwrap();
+
+        (near_rpc, evm_rpc, evm)
+    }
+
+    /// Decodes the last raw transaction sent to `evm_rpc` and checks that it was signed by the
+    /// address derived for `alice.test.near` and `path`.
+    pub(super) async fn last_signed_transaction(
+        evm_rpc: &FakeEvmRpc,
+        evm: &EVM<Http>,
+        path: &str,
+    ) -> (TypedTransaction, ethers_core::types::Signature) {
+        let raw: Bytes = serde_json::from_value(
+            evm_rpc.requests("eth_sendRawTransaction").last().unwrap()[0].clone(),
+        )
+        .unwrap();
+        let (transaction, signature) = TypedTransaction::decode_signed(&Rlp::new(&raw)).unwrap();
+
+        let from: H160 = evm
+            .derive_address("alice.test.near", &path.parse().unwrap())
+            .await
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(signature.recover(transaction.sighash()).unwrap(), from);
+
+        (transaction, signature)
+    }
+
+    #[tokio::test]
+    async fn test_handle_legacy_transaction() -> Result<()> {
+        let (_near_rpc, evm_rpc, evm) = mock_evm().await;
+
+        let transaction_request = TypedTransaction::Legacy(
+            TransactionRequest::new()
+                .to("0x4174678c78fEaFd778c1ff319D5D326701449b25"
+                    .parse::<H160>()
+                    .unwrap())
+                .value(1000),
+        );
+        evm.handle_transaction(transaction_request, "eth".parse().unwrap())
+            .await?;
+
+        let (transaction, signature) = last_signed_transaction(&evm_rpc, &evm, "eth").await;
+        assert!(matches!(transaction, TypedTransaction::Legacy(_)));
+        assert_eq!(transaction.gas_price(), Some(U256::from(3_000_000_000u64)));
+        assert_eq!(transaction.chain_id(), Some(11155111.into()));
+        // EIP-155 replay protection: v = 35 + 2 * chain_id + y_parity.
+        assert!([35 + 2 * 11155111, 36 + 2 * 11155111].contains(&signature.v));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_attach_gas_and_nonce_keeps_caller_fields() -> Result<()> {
+        let (_near_rpc, evm_rpc, evm) = mock_evm().await;
+        let from = evm
+            .derive_address("alice.test.near", &"eth".parse().unwrap())
+            .await?;
+        let access_list = AccessList(vec![AccessListItem {
+            address: H160::repeat_byte(1),
+            storage_keys: vec![H256::repeat_byte(2)],
+        }]);
+
+        let transaction_request = TypedTransaction::Eip2930(Eip2930TransactionRequest::new(
+            TransactionRequest::new()
+                .to(H160::repeat_byte(3))
+                .gas(100_000)
+                .nonce(9),
+            access_list.clone(),
+        ));
+        let transaction = evm
+            .attach_gas_and_nonce(&transaction_request, &from)
+            .await?;
+        let TypedTransaction::Eip2930(request) = &transaction else {
+            panic!("transaction type changed: {:?}", transaction);
+        };
+        assert_eq!(request.access_list, access_list);
+        assert_eq!(transaction.gas(), Some(&U256::from(100_000)));
+        assert_eq!(transaction.nonce(), Some(&U256::from(9)));
+        assert_eq!(transaction.gas_price(), Some(U256::from(3_000_000_000u64)));
+        assert!(evm_rpc.requests("eth_estimateGas").is_empty());
+        assert!(evm_rpc.requests("eth_getTransactionCount").is_empty());
+
+        evm.handle_transaction(transaction_request, "eth".parse().unwrap())
+            .await?;
+        let (transaction, _) = last_signed_transaction(&evm_rpc, &evm, "eth").await;
+        assert_eq!(transaction.access_list(), Some(&access_list));
+
+        let transaction_request = TypedTransaction::Eip1559(
+            Eip1559TransactionRequest::new()
+                .to(H160::repeat_byte(3))
+                .max_priority_fee_per_gas(2)
+                .access_list(access_list.clone()),
+        );
+        let transaction = evm
+            .attach_gas_and_nonce(&transaction_request, &from)
+            .await?;
+        let TypedTransaction::Eip1559(request) = &transaction else {
+            panic!("transaction type changed: {:?}", transaction);
+        };
+        assert_eq!(request.max_priority_fee_per_gas, Some(U256::from(2)));
+        // Twice the base fee of 7 plus the caller's priority fee, not the estimated 1 gwei.
+        assert_eq!(request.max_fee_per_gas, Some(U256::from(16)));
+        assert_eq!(request.access_list, access_list);
+        assert_eq!(transaction.gas(), Some(&U256::from(0x5208)));
+        assert_eq!(transaction.nonce(), Some(&U256::from(5)));
+
+        // Filling in a transaction doesn't reserve its nonce, so sending one leaves no gap.
+        evm.handle_transaction(transaction_request, "eth".parse().unwrap())
+            .await?;
+        let (transaction, _) = last_signed_transaction(&evm_rpc, &evm, "eth").await;
+        assert_eq!(transaction.nonce(), Some(&U256::from(5)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deploy_contract() -> Result<()> {
+        let (_near_rpc, evm_rpc, evm) = mock_evm().await;
+        let from: H160 = evm
+            .derive_address("alice.test.near", &"eth".parse().unwrap())
+            .await?
+            .parse()
+            .unwrap();
+        let init_code = Bytes::from(vec![0x60, 0x00, 0x60, 0x00, 0xf3]);
+        let mined = |status: u64| {
+            evm_rpc.set_response(
+                "eth_getTransactionByHash",
+                json!(Transaction {
+                    hash: H256::repeat_byte(0xab),
+                    block_number: Some(1.into()),
+                    ..Default::default()
+                }),
+            );
+            evm_rpc.set_response(
+                "eth_getTransactionReceipt",
+                json!(TransactionReceipt {
+                    transaction_hash: H256::repeat_byte(0xab),
+                    block_number: Some(1.into()),
+                    status: Some(status.into()),
+                    ..Default::default()
+                }),
+            );
+        };
+        let deployment_request =
+            TypedTransaction::Eip1559(Eip1559TransactionRequest::new().data(init_code.clone()));
+
+        mined(1);
+        let deployed = evm
+            .deploy_contract(
+                deployment_request.clone(),
+                Deployment::Create,
+                "eth".parse().unwrap(),
+            )
+            .await?;
+        assert_eq!(deployed.address, get_contract_address(from, 5));
+        let (transaction, _) = last_signed_transaction(&evm_rpc, &evm, "eth").await;
+        assert_eq!(transaction.to(), None);
+        assert_eq!(transaction.data(), Some(&init_code));
+
+        let salt = H256::repeat_byte(7);
+        let deployed = evm
+            .deploy_contract(
+                deployment_request.clone(),
+                Deployment::Create2 {
+                    deployer: DETERMINISTIC_DEPLOYER,
+                    salt,
+                },
+                "eth".parse().unwrap(),
+            )
+            .await?;
+        assert_eq!(
+            deployed.address,
+            get_create2_address(DETERMINISTIC_DEPLOYER, salt, &init_code)
+        );
+        let (transaction, _) = last_signed_transaction(&evm_rpc, &evm, "eth").await;
+        assert_eq!(transaction.to_addr(), Some(&DETERMINISTIC_DEPLOYER));
+        assert_eq!(
+            transaction.data().unwrap().to_vec(),
+            [salt.as_bytes(), &init_code].concat()
+        );
+
+        mined(0);
+        let result = evm
+            .deploy_contract(
+                deployment_request,
+                Deployment::Create,
+                "eth".parse().unwrap(),
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(ChainSignatureError::EvmReverted { .. })
+        ));
+
+        let result = evm
+            .deploy_contract(
+                TypedTransaction::Eip1559(Eip1559TransactionRequest::new().to(from)),
+                Deployment::Create,
+                "eth".parse().unwrap(),
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(ChainSignatureError::EvmTransaction(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_transactions_get_consecutive_nonces() -> Result<()> {
+        let (_near_rpc, evm_rpc, evm) = mock_evm().await;
+        let transaction_request =
+            TypedTransaction::Eip1559(Eip1559TransactionRequest::new().to(H160::repeat_byte(3)));
+        let sent_nonces = || -> Vec<U256> {
+            evm_rpc
+                .requests("eth_sendRawTransaction")
+                .iter()
+                .map(|params| {
+                    let raw: Bytes = serde_json::from_value(params[0].clone()).unwrap();
+                    let (transaction, _) =
+                        TypedTransaction::decode_signed(&Rlp::new(&raw)).unwrap();
+                    *transaction.nonce().unwrap()
+                })
+                .collect()
+        };
+
+        futures::future::try_join_all(
+            (0..3).map(|_| {
+                evm.handle_transaction(transaction_request.clone(), "eth".parse().unwrap())
+            }),
+        )
+        .await?;
+        let mut nonces = sent_nonces();
+        nonces.sort();
+        assert_eq!(nonces, vec![5.into(), 6.into(), 7.into()]);
+        assert_eq!(evm_rpc.requests("eth_getTransactionCount").len(), 1);
+        assert_eq!(evm_rpc.requests("eth_getTransactionCount")[0][1], "pending");
+
+        // Another client used nonces 8 and 9, so 8 is rejected and the next attempt resyncs.
+        evm_rpc.push_error("eth_sendRawTransaction", -32000, "nonce too low", None);
+        evm_rpc.set_response("eth_getTransactionCount", json!("0xa"));
+        evm.handle_transaction(transaction_request, "eth".parse().unwrap())
+            .await?;
+        assert_eq!(sent_nonces()[3..], [8.into(), 10.into()]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_speed_up_and_cancel() -> Result<()> {
+        let (_near_rpc, evm_rpc, evm) = mock_evm().await;
+        let from: H160 = evm
+            .derive_address("alice.test.near", &"eth".parse().unwrap())
+            .await?
+            .parse()
+            .unwrap();
+        let tx_hash = H256::repeat_byte(0xcd);
+        let pending = Transaction {
+            hash: tx_hash,
+            from,
+            to: Some(H160::repeat_byte(3)),
+            value: 1000.into(),
+            input: Bytes::from(vec![1, 2, 3]),
+            nonce: 3.into(),
+            gas: 50_000.into(),
+            transaction_type: Some(2.into()),
+            max_fee_per_gas: Some(100_000_000_000u64.into()),
+            max_priority_fee_per_gas: Some(2_000_000_000u64.into()),
+            chain_id: Some(11155111.into()),
+            ..Default::default()
+        };
+        evm_rpc.set_response("eth_getTransactionByHash", json!(pending));
+
+        evm.speed_up(tx_hash, "eth".parse().unwrap()).await?;
+        let (transaction, _) = last_signed_transaction(&evm_rpc, &evm, "eth").await;
+        let TypedTransaction::Eip1559(request) = &transaction else {
+            panic!("transaction type changed: {:?}", transaction);
+        };
+        assert_eq!(transaction.nonce(), Some(&3.into()));
+        assert_eq!(transaction.to_addr(), Some(&H160::repeat_byte(3)));
+        assert_eq!(transaction.value(), Some(&1000.into()));
+        assert_eq!(transaction.data(), Some(&Bytes::from(vec![1, 2, 3])));
+        assert_eq!(transaction.gas(), Some(&50_000.into()));
+        assert_eq!(
+            request.max_priority_fee_per_gas,
+            Some(2_200_000_000u64.into())
+        );
+        assert_eq!(request.max_fee_per_gas, Some(110_000_000_000u64.into()));
+
+        // A legacy transaction priced below the current gas price of 3 gwei.
+        evm_rpc.set_response(
+            "eth_getTransactionByHash",
+            json!(Transaction {
+                transaction_type: None,
+                gas_price: Some(1_000_000_000u64.into()),
+                max_fee_per_gas: None,
+                max_priority_fee_per_gas: None,
+                ..pending.clone()
+            }),
+        );
+        evm.cancel(tx_hash, "eth".parse().unwrap()).await?;
+        let (transaction, _) = last_signed_transaction(&evm_rpc, &evm, "eth").await;
+        assert!(matches!(transaction, TypedTransaction::Legacy(_)));
+        assert_eq!(transaction.nonce(), Some(&3.into()));
+        assert_eq!(transaction.to_addr(), Some(&from));
+        assert_eq!(transaction.value(), Some(&0.into()));
+        assert!(transaction.data().cloned().unwrap_or_default().is_empty());
+        assert_eq!(transaction.gas(), Some(&21_000.into()));
+        assert_eq!(transaction.gas_price(), Some(3_000_000_000u64.into()));
+        assert!(evm_rpc.requests("eth_estimateGas").is_empty());
+
+        evm_rpc.set_response(
+            "eth_getTransactionByHash",
+            json!(Transaction {
+                block_number: Some(1.into()),
+                ..pending.clone()
+            }),
+        );
+        let result = evm.speed_up(tx_hash, "eth".parse().unwrap()).await;
+        assert!(matches!(
+            result,
+            Err(ChainSignatureError::EvmTransaction(_))
+        ));
+
+        evm_rpc.set_response(
+            "eth_getTransactionByHash",
+            json!(Transaction {
+                from: H160::repeat_byte(9),
+                ..pending
+            }),
+        );
+        let result = evm.cancel(tx_hash, "eth".parse().unwrap()).await;
+        assert!(matches!(
+            result,
+            Err(ChainSignatureError::EvmTransaction(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_transaction_rejects_wrong_signer() {
+        let account_id: AccountId = "alice.test.near".parse().unwrap();
+        let contract_id: AccountId = "signer.test.near".parse().unwrap();
+        let near_rpc = FakeNearRpc::start(
+            MockSigner::from_bytes(&[1; 32]).unwrap(),
+            contract_id.clone(),
+        )
+        .await;
+        let evm_rpc = FakeEvmRpc::start().await;
+
+        evm_rpc.set_response("eth_getTransactionCount", json!("0x0"));
+        evm_rpc.set_response("eth_estimateGas", json!("0x5208"));
+        evm_rpc.set_response("eth_chainId", json!("0x1"));
+
+        let evm = EVM::new(
+            Provider::<Http>::try_from(evm_rpc.url()).unwrap(),
+            NearAuthentication::new(
+                NearNetwork::custom(near_rpc.url()),
+                InMemorySigner::from_seed(account_id, KeyType::ED25519, "test"),
+            ),
+            contract_id,
+            KeyVersion::V0,
+        )
+        .unwrap()
+        .with_root_public_key(KeyVersion::V0, MockSigner::default().root_public_key())
+        .with_fee_strategy(FeeStrategy::Fixed {
+            max_fee_per_gas: 2_000_000_000u64.into(),
+            max_priority_fee_per_gas: 1_000_000_000u64.into(),
+        });
+
+        let transaction_request = TypedTransaction::Eip1559(
+            Eip1559TransactionRequest::new().to("0x4174678c78fEaFd778c1ff319D5D326701449b25"
+                .parse::<ethers_core::types::NameOrAddress>()
+                .unwrap()),
+        );
+
+        let result = evm
+            .handle_transaction(transaction_request, "eth".parse().unwrap())
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(ChainSignatureError::SignatureVerification(_))
+        ));
+        assert!(evm_rpc.requests("eth_sendRawTransaction").is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires NEAR testnet credentials"]
     async fn test_handle_transaction() {
         dotenv().ok();
         let account_id: AccountId = std::env::var("NEAR_ACCOUNT_ID").unwrap().parse().unwrap();
@@ -170,13 +1114,14 @@ mod tests {
 
         let evm = EVM::new(
             Provider::<Http>::try_from(eth_rpc_url).unwrap(),
-            NearAuthentication {
-                network: NearNetwork::Testnet,
-                account_id: account_id.clone(),
-                key_pair: InMemorySigner::from_secret_key(account_id, private_key),
-            },
+            NearAuthentication::new(
+                NearNetwork::Testnet,
+                InMemorySigner::from_secret_key(account_id, private_key),
+            ),
             contract_id,
-        );
+            KeyVersion::V0,
+        )
+        .unwrap();
 
         let transaction_request = TypedTransaction::Eip1559(
             Eip1559TransactionRequest::new()
@@ -187,7 +1132,7 @@ mod tests {
         );
 
         let result = evm
-            .handle_transaction(transaction_request, "eth".to_string())
+            .handle_transaction(transaction_request, "eth".parse().unwrap())
             .await;
 
         assert!(result.is_ok());
@@ -0,0 +1,318 @@
+//! Calls to any contract described by its ABI, e.g. one loaded with `serde_json` from a build
+//! artifact or written with `ethers_core::abi::parse_abi`.
+
+use ethers_core::abi::{Abi, Function, ParamType, Token};
+use ethers_core::types::{Bytes, H160, I256, U256};
+use ethers_providers::JsonRpcClient;
+use utils::types::DerivationPath;
+
+use super::{SentTransaction, EVM};
+use crate::error::{ChainSignatureError, Result};
+
+impl<P: JsonRpcClient> EVM<P> {
+    /// Calls `function` of the contract at `address` with `args`, sending `value` wei from the
+    /// address derived for `path`. Overloads are told apart by the types of `args`.
+    ///
+    /// The call is simulated with `eth_call` first, so a transaction that would revert fails
+    /// with [`ChainSignatureError::EvmCallReverted`] before the MPC network is asked, and paid,
+    /// to sign it. Custom errors declared in `abi` are decoded into the revert reason.
+    pub async fn call_contract(
+        &self,
+        address: H160,
+        abi: &Abi,
+        function: &str,
+        args: &[Token],
+        value: U256,
+        path: DerivationPath,
+    ) -> Result<SentTransaction> {
+        let data = encode_call(find_function(abi, function, args)?, args)?;
+        let from = self.own_address(&path).await?;
+        let mut transaction = self.contract_transaction(address, data);
+        transaction.set_from(from).set_value(value);
+
+        self.eth_call(&transaction)
+            .await
+            .map_err(|err| decode_custom_error(abi, err))?;
+
+        self.handle_transaction(transaction, path).await
+    }
+
+    /// Calls the view `function` of the contract at `address` with `args` and returns its
+    /// decoded outputs. Reverts are decoded like in [`Self::call_contract`].
+    pub async fn read_contract(
+        &self,
+        address: H160,
+        abi: &Abi,
+        function: &str,
+        args: &[Token],
+    ) -> Result<Vec<Token>> {
+        let function = find_function(abi, function, args)?;
+        let output = self
+            .call(address, encode_call(function, args)?)
+            .await
+            .map_err(|err| decode_custom_error(abi, err))?;
+
+        function.decode_output(&output).map_err(|e| {
+            ChainSignatureError::EvmTransaction(format!(
+                "invalid output of {}: {}",
+                function.signature(),
+                e
+            ))
+        })
+    }
+}
+
+fn find_function<'a>(abi: &'a Abi, name: &str, args: &[Token]) -> Result<&'a Function> {
+    let overloads = abi.functions_by_name(name).map_err(|_| {
+        ChainSignatureError::EvmTransaction(format!("function {} is not in the ABI", name))
+    })?;
+
+    overloads
+        .iter()
+        .find(|function| {
+            let inputs: Vec<ParamType> = function.inputs.iter().map(|p| p.kind.clone()).collect();
+            Token::types_check(args, &inputs)
+        })
+        .ok_or_else(|| {
+            ChainSignatureError::EvmTransaction(format!(
+                "no overload of {} takes ({})",
+                name,
+                args.iter().map(format_token).collect::<Vec<_>>().join(", ")
+            ))
+        })
+}
+
+fn encode_call(function: &Function, args: &[Token]) -> Result<Vec<u8>> {
+    function.encode_input(args).map_err(|e| {
+        ChainSignatureError::EvmTransaction(format!(
+            "invalid arguments for {}: {}",
+            function.signature(),
+            e
+        ))
+    })
+}
+
+/// Replaces the reason of a revert with the custom error of `abi` it carries, such as
+/// `InsufficientBalance(10, 20)`, if any.
+fn decode_custom_error(abi: &Abi, err: ChainSignatureError) -> ChainSignatureError {
+    let ChainSignatureError::EvmCallReverted { reason, data } = err else {
+        return err;
+    };
+
+    let custom_error = abi.errors().find_map(|error| {
+        let arguments = data.strip_prefix(&error.signature()[..4])?;
+        let tokens = error.decode(arguments).ok()?;
+        Some(format!(
+            "{}({})",
+            error.name,
+            tokens
+                .iter()
+                .map(format_token)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    });
+
+    ChainSignatureError::EvmCallReverted {
+        reason: custom_error.or(reason),
+        data,
+    }
+}
+
+/// Formats a token the way it would be written in Solidity, unlike its `Display`, which prints
+/// numbers in hex.
+fn format_token(token: &Token) -> String {
+    match token {
+        Token::Uint(value) => value.to_string(),
+        Token::Int(value) => I256::from_raw(*value).to_string(),
+        Token::Address(address) => format!("{:?}", address),
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => Bytes::from(bytes.clone()).to_string(),
+        Token::String(string) => format!("{:?}", string),
+        Token::Bool(value) => value.to_string(),
+        Token::Array(tokens) | Token::FixedArray(tokens) => format!(
+            "[{}]",
+            tokens
+                .iter()
+                .map(format_token)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Token::Tuple(tokens) => format!(
+            "({})",
+            tokens
+                .iter()
+                .map(format_token)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm::{
+        tests::{last_signed_transaction, mock_evm},
+        TransactionType,
+    };
+    use ethers_core::abi::{encode, parse_abi};
+    use ethers_core::types::transaction::eip2718::TypedTransaction;
+    use serde_json::json;
+
+    fn vault_abi() -> Abi {
+        parse_abi(&[
+            "function deposit(uint256 amount) payable",
+            "function deposit(address to, uint256 amount) payable",
+            "function balances(address owner) view returns (uint256, bool)",
+            "error InsufficientBalance(uint256 available, uint256 required)",
+        ])
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_read_contract() -> Result<()> {
+        let (_near_rpc, evm_rpc, evm) = mock_evm().await;
+        let abi = vault_abi();
+        let vault = H160::repeat_byte(0x99);
+        let owner = H160::repeat_byte(1);
+
+        evm_rpc.push_response(
+            "eth_call",
+            json!(Bytes::from(encode(&[
+                Token::Uint(42.into()),
+                Token::Bool(true)
+            ]))),
+        );
+        let outputs = evm
+            .read_contract(vault, &abi, "balances", &[Token::Address(owner)])
+            .await?;
+        assert_eq!(outputs, vec![Token::Uint(42.into()), Token::Bool(true)]);
+
+        let result = evm.read_contract(vault, &abi, "withdraw", &[]).await;
+        assert!(matches!(
+            result,
+            Err(ChainSignatureError::EvmTransaction(ref message)) if message.contains("withdraw")
+        ));
+
+        let result = evm
+            .read_contract(vault, &abi, "balances", &[Token::Bool(true)])
+            .await;
+        assert!(matches!(
+            result,
+            Err(ChainSignatureError::EvmTransaction(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_call_contract_simulates_before_signing() -> Result<()> {
+        let (near_rpc, evm_rpc, evm) = mock_evm().await;
+        let abi = vault_abi();
+        let vault = H160::repeat_byte(0x99);
+        let to = H160::repeat_byte(2);
+        let from = evm.own_address(&"eth".parse().unwrap()).await?;
+        let args = [Token::Address(to), Token::Uint(5.into())];
+
+        evm_rpc.set_response("eth_call", json!("0x"));
+        evm.call_contract(
+            vault,
+            &abi,
+            "deposit",
+            &args,
+            100.into(),
+            "eth".parse().unwrap(),
+        )
+        .await?;
+        let simulation = &evm_rpc.requests("eth_call")[0][0];
+        assert_eq!(simulation["from"], json!(from));
+        assert_eq!(simulation["value"], json!("0x64"));
+        let (transaction, _) = last_signed_transaction(&evm_rpc, &evm, "eth").await;
+        assert_eq!(transaction.to_addr(), Some(&vault));
+        assert_eq!(transaction.value(), Some(&100.into()));
+        // The overload taking an address and an amount.
+        let deposit = &abi.functions_by_name("deposit").unwrap()[1];
+        assert_eq!(
+            transaction.data().unwrap().to_vec(),
+            deposit.encode_input(&args).unwrap()
+        );
+        let sign_calls = near_rpc.function_calls().len();
+        let sent_transactions = evm_rpc.requests("eth_sendRawTransaction").len();
+
+        let custom_error = abi
+            .error("InsufficientBalance")
+            .unwrap()
+            .encode(&[Token::Uint(10.into()), Token::Uint(20.into())])
+            .unwrap();
+        evm_rpc.set_error(
+            "eth_call",
+            3,
+            "execution reverted",
+            Some(json!(Bytes::from(custom_error))),
+        );
+        let result = evm
+            .call_contract(
+                vault,
+                &abi,
+                "deposit",
+                &args,
+                100.into(),
+                "eth".parse().unwrap(),
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(ChainSignatureError::EvmCallReverted { reason: Some(ref reason), .. })
+                if reason == "InsufficientBalance(10, 20)"
+        ));
+        // Nothing was signed or sent for the reverting call.
+        assert_eq!(near_rpc.function_calls().len(), sign_calls);
+        assert_eq!(
+            evm_rpc.requests("eth_sendRawTransaction").len(),
+            sent_transactions
+        );
+
+        evm_rpc.set_error("eth_call", 3, "execution reverted", None);
+        let result = evm
+            .call_contract(
+                vault,
+                &abi,
+                "deposit",
+                &[Token::Uint(5.into())],
+                U256::zero(),
+                "eth".parse().unwrap(),
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(ChainSignatureError::EvmCallReverted { reason: None, .. })
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_call_contract_uses_the_configured_transaction_type() -> Result<()> {
+        let (_near_rpc, evm_rpc, evm) = mock_evm().await;
+        let evm = evm.with_transaction_type(TransactionType::Legacy);
+        let vault = H160::repeat_byte(0x99);
+
+        evm_rpc.set_response("eth_call", json!("0x"));
+        evm.call_contract(
+            vault,
+            &vault_abi(),
+            "deposit",
+            &[Token::Uint(5.into())],
+            100.into(),
+            "eth".parse().unwrap(),
+        )
+        .await?;
+        let (transaction, _) = last_signed_transaction(&evm_rpc, &evm, "eth").await;
+        assert!(matches!(transaction, TypedTransaction::Legacy(_)));
+        assert_eq!(transaction.to_addr(), Some(&vault));
+        assert_eq!(transaction.value(), Some(&100.into()));
+        assert_eq!(transaction.gas_price(), Some(U256::from(3_000_000_000u64)));
+
+        Ok(())
+    }
+}
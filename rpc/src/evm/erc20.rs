@@ -0,0 +1,235 @@
+//! ERC-20 token transfers and queries. Writes are signed and sent like any other transaction
+//! with [`EVM::handle_transaction`].
+
+use ethers_core::abi::{decode, ParamType, Token};
+use ethers_core::types::{H160, U256};
+use ethers_providers::JsonRpcClient;
+use utils::types::DerivationPath;
+
+use super::{calldata, decode_output, SentTransaction, EVM};
+use crate::error::{ChainSignatureError, Result};
+
+impl<P: JsonRpcClient> EVM<P> {
+    /// Transfers `amount` base units of `token` from the address derived for `path` to `to`.
+    pub async fn erc20_transfer(
+        &self,
+        token: H160,
+        to: H160,
+        amount: U256,
+        path: DerivationPath,
+    ) -> Result<SentTransaction> {
+        let data = calldata(
+            "transfer",
+            &[ParamType::Address, ParamType::Uint(256)],
+            &[Token::Address(to), Token::Uint(amount)],
+        );
+
+        self.handle_transaction(self.contract_transaction(token, data), path)
+            .await
+    }
+
+    /// Allows `spender` to transfer up to `amount` base units of `token` from the address derived
+    /// for `path`.
+    pub async fn erc20_approve(
+        &self,
+        token: H160,
+        spender: H160,
+        amount: U256,
+        path: DerivationPath,
+    ) -> Result<SentTransaction> {
+        let data = calldata(
+            "approve",
+            &[ParamType::Address, ParamType::Uint(256)],
+            &[Token::Address(spender), Token::Uint(amount)],
+        );
+
+        self.handle_transaction(self.contract_transaction(token, data), path)
+            .await
+    }
+
+    /// The balance of `owner` in base units.
+    pub async fn erc20_balance_of(&self, token: H160, owner: H160) -> Result<U256> {
+        let data = calldata("balanceOf", &[ParamType::Address], &[Token::Address(owner)]);
+
+        decode_uint(&self.call(token, data).await?)
+    }
+
+    /// How many base units `spender` may still transfer from `owner`.
+    pub async fn erc20_allowance(&self, token: H160, owner: H160, spender: H160) -> Result<U256> {
+        let data = calldata(
+            "allowance",
+            &[ParamType::Address, ParamType::Address],
+            &[Token::Address(owner), Token::Address(spender)],
+        );
+
+        decode_uint(&self.call(token, data).await?)
+    }
+
+    pub async fn erc20_decimals(&self, token: H160) -> Result<u8> {
+        let decimals = decode_uint(&self.call(token, calldata("decimals", &[], &[])).await?)?;
+
+        u8::try_from(decimals).map_err(|_| {
+            ChainSignatureError::EvmTransaction(format!("invalid token decimals {}", decimals))
+        })
+    }
+
+    /// The token symbol. Also accepts the `bytes32` symbols of early tokens such as MKR.
+    pub async fn erc20_symbol(&self, token: H160) -> Result<String> {
+        let output = self.call(token, calldata("symbol", &[], &[])).await?;
+
+        if let Ok(tokens) = decode(&[ParamType::String], &output) {
+            if let [Token::String(symbol)] = tokens.as_slice() {
+                return Ok(symbol.clone());
+            }
+        }
+        if output.len() == 32 {
+            return Ok(String::from_utf8_lossy(&output)
+                .trim_end_matches('\0')
+                .to_string());
+        }
+
+        Err(ChainSignatureError::EvmTransaction(format!(
+            "invalid token symbol {}",
+            output
+        )))
+    }
+
+    /// Formats `amount` base units of `token` with its decimals and symbol, e.g. `1.5 USDC`.
+    pub async fn erc20_format_amount(&self, token: H160, amount: U256) -> Result<String> {
+        let decimals = self.erc20_decimals(token).await?;
+        let symbol = self.erc20_symbol(token).await?;
+
+        Ok(format!(
+            "{} {}",
+            format_token_amount(amount, decimals),
+            symbol
+        ))
+    }
+}
+
+/// Formats `amount` base units as a decimal number of tokens, without trailing zeros.
+///
+/// # Example
+///
+/// ```
+/// use rpc::evm::erc20::format_token_amount;
+///
+/// assert_eq!(format_token_amount(1_500_000.into(), 6), "1.5");
+/// assert_eq!(format_token_amount(42.into(), 0), "42");
+/// assert_eq!(format_token_amount(1.into(), 18), "0.000000000000000001");
+/// ```
+pub fn format_token_amount(amount: U256, decimals: u8) -> String {
+    // 10^78 and above don't fit, but then every amount is below one token.
+    let (whole, fraction) = match U256::from(10).checked_pow(decimals.into()) {
+        Some(unit) => amount.div_mod(unit),
+        None => (U256::zero(), amount),
+    };
+
+    if fraction.is_zero() {
+        return whole.to_string();
+    }
+
+    let fraction = format!("{:0>width$}", fraction, width = decimals as usize);
+    format!("{}.{}", whole, fraction.trim_end_matches('0'))
+}
+
+fn decode_uint(output: &[u8]) -> Result<U256> {
+    let value = decode_output(output, ParamType::Uint(256))?;
+
+    Ok(value.into_uint().expect("decoded as a uint256"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm::{
+        tests::{last_signed_transaction, mock_evm},
+        TransactionType,
+    };
+    use ethers_core::abi::encode;
+    use ethers_core::types::{transaction::eip2718::TypedTransaction, Bytes};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_erc20() -> Result<()> {
+        let (_near_rpc, evm_rpc, evm) = mock_evm().await;
+        let token = H160::repeat_byte(0x20);
+        let (owner, spender) = (H160::repeat_byte(1), H160::repeat_byte(2));
+
+        evm.erc20_transfer(token, spender, 1_500_000.into(), "eth".parse().unwrap())
+            .await?;
+        let (transaction, _) = last_signed_transaction(&evm_rpc, &evm, "eth").await;
+        assert_eq!(transaction.to_addr(), Some(&token));
+        assert_eq!(
+            transaction.data().unwrap().to_vec(),
+            calldata(
+                "transfer",
+                &[ParamType::Address, ParamType::Uint(256)],
+                &[Token::Address(spender), Token::Uint(1_500_000.into())]
+            )
+        );
+        // transfer(address,uint256)
+        assert_eq!(transaction.data().unwrap()[..4], [0xa9, 0x05, 0x9c, 0xbb]);
+
+        evm.erc20_approve(token, spender, U256::MAX, "eth".parse().unwrap())
+            .await?;
+        let (transaction, _) = last_signed_transaction(&evm_rpc, &evm, "eth").await;
+        // approve(address,uint256)
+        assert_eq!(transaction.data().unwrap()[..4], [0x09, 0x5e, 0xa7, 0xb3]);
+
+        evm_rpc.push_response(
+            "eth_call",
+            json!(Bytes::from(encode(&[Token::Uint(7.into())]))),
+        );
+        assert_eq!(evm.erc20_balance_of(token, owner).await?, 7.into());
+        let request = &evm_rpc.requests("eth_call")[0][0];
+        assert_eq!(request["to"], json!(token));
+        assert_eq!(
+            request["data"],
+            json!(Bytes::from(calldata(
+                "balanceOf",
+                &[ParamType::Address],
+                &[Token::Address(owner)]
+            )))
+        );
+
+        evm_rpc.push_response(
+            "eth_call",
+            json!(Bytes::from(encode(&[Token::Uint(9.into())]))),
+        );
+        assert_eq!(evm.erc20_allowance(token, owner, spender).await?, 9.into());
+
+        evm_rpc.push_response(
+            "eth_call",
+            json!(Bytes::from(encode(&[Token::Uint(6.into())]))),
+        );
+        evm_rpc.push_response(
+            "eth_call",
+            json!(Bytes::from(encode(&[Token::String("USDC".to_string())]))),
+        );
+        assert_eq!(
+            evm.erc20_format_amount(token, 1_500_000.into()).await?,
+            "1.5 USDC"
+        );
+
+        let mut mkr = [0u8; 32];
+        mkr[..3].copy_from_slice(b"MKR");
+        evm_rpc.push_response("eth_call", json!(Bytes::from(mkr.to_vec())));
+        assert_eq!(evm.erc20_symbol(token).await?, "MKR");
+
+        evm_rpc.push_response("eth_call", json!(Bytes::from(vec![1, 2])));
+        assert!(matches!(
+            evm.erc20_balance_of(token, owner).await,
+            Err(ChainSignatureError::EvmTransaction(_))
+        ));
+
+        let evm = evm.with_transaction_type(TransactionType::Eip2930);
+        evm.erc20_transfer(token, spender, 1.into(), "eth".parse().unwrap())
+            .await?;
+        let (transaction, _) = last_signed_transaction(&evm_rpc, &evm, "eth").await;
+        assert!(matches!(transaction, TypedTransaction::Eip2930(_)));
+        assert_eq!(transaction.to_addr(), Some(&token));
+
+        Ok(())
+    }
+}
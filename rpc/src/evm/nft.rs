@@ -0,0 +1,403 @@
+//! ERC-721 and ERC-1155 transfers, approvals and queries. Transfers are made from the address
+//! derived for `path` and sent with [`EVM::handle_transaction`].
+
+use ethers_core::abi::{ParamType, Token};
+use ethers_core::types::{Bytes, H160, U256};
+use ethers_providers::JsonRpcClient;
+use utils::types::DerivationPath;
+
+use super::{calldata, decode_output, SentTransaction, EVM};
+use crate::error::{ChainSignatureError, Result};
+
+impl<P: JsonRpcClient> EVM<P> {
+    /// Transfers the ERC-721 token `token_id` of `contract` to `to` with `safeTransferFrom`,
+    /// which reverts if `to` is a contract that doesn't accept NFTs.
+    pub async fn erc721_safe_transfer_from(
+        &self,
+        contract: H160,
+        to: H160,
+        token_id: U256,
+        path: DerivationPath,
+    ) -> Result<SentTransaction> {
+        let from = self.own_address(&path).await?;
+        let data = calldata(
+            "safeTransferFrom",
+            &[ParamType::Address, ParamType::Address, ParamType::Uint(256)],
+            &[
+                Token::Address(from),
+                Token::Address(to),
+                Token::Uint(token_id),
+            ],
+        );
+
+        self.handle_transaction(self.contract_transaction(contract, data), path)
+            .await
+    }
+
+    /// Transfers `amount` of the ERC-1155 token `id` of `contract` to `to`. `data` is passed on
+    /// to the receiver hook of `to` if it is a contract.
+    pub async fn erc1155_safe_transfer_from(
+        &self,
+        contract: H160,
+        to: H160,
+        id: U256,
+        amount: U256,
+        data: Bytes,
+        path: DerivationPath,
+    ) -> Result<SentTransaction> {
+        let from = self.own_address(&path).await?;
+        let data = calldata(
+            "safeTransferFrom",
+            &[
+                ParamType::Address,
+                ParamType::Address,
+                ParamType::Uint(256),
+                ParamType::Uint(256),
+                ParamType::Bytes,
+            ],
+            &[
+                Token::Address(from),
+                Token::Address(to),
+                Token::Uint(id),
+                Token::Uint(amount),
+                Token::Bytes(data.to_vec()),
+            ],
+        );
+
+        self.handle_transaction(self.contract_transaction(contract, data), path)
+            .await
+    }
+
+    /// Transfers `amounts[i]` of each ERC-1155 token `ids[i]` of `contract` to `to` in a single
+    /// transaction.
+    pub async fn erc1155_safe_batch_transfer_from(
+        &self,
+        contract: H160,
+        to: H160,
+        ids: Vec<U256>,
+        amounts: Vec<U256>,
+        data: Bytes,
+        path: DerivationPath,
+    ) -> Result<SentTransaction> {
+        if ids.len() != amounts.len() {
+            return Err(ChainSignatureError::EvmTransaction(format!(
+                "{} token ids but {} amounts",
+                ids.len(),
+                amounts.len()
+            )));
+        }
+
+        let from = self.own_address(&path).await?;
+        let data = calldata(
+            "safeBatchTransferFrom",
+            &[
+                ParamType::Address,
+                ParamType::Address,
+                uint_array(),
+                uint_array(),
+                ParamType::Bytes,
+            ],
+            &[
+                Token::Address(from),
+                Token::Address(to),
+                Token::Array(ids.into_iter().map(Token::Uint).collect()),
+                Token::Array(amounts.into_iter().map(Token::Uint).collect()),
+                Token::Bytes(data.to_vec()),
+            ],
+        );
+
+        self.handle_transaction(self.contract_transaction(contract, data), path)
+            .await
+    }
+
+    /// Allows or disallows `operator` to transfer every token of `contract` held by the address
+    /// derived for `path`. ERC-721 and ERC-1155 share this function.
+    pub async fn nft_set_approval_for_all(
+        &self,
+        contract: H160,
+        operator: H160,
+        approved: bool,
+        path: DerivationPath,
+    ) -> Result<SentTransaction> {
+        let data = calldata(
+            "setApprovalForAll",
+            &[ParamType::Address, ParamType::Bool],
+            &[Token::Address(operator), Token::Bool(approved)],
+        );
+
+        self.handle_transaction(self.contract_transaction(contract, data), path)
+            .await
+    }
+
+    pub async fn nft_is_approved_for_all(
+        &self,
+        contract: H160,
+        owner: H160,
+        operator: H160,
+    ) -> Result<bool> {
+        let data = calldata(
+            "isApprovedForAll",
+            &[ParamType::Address, ParamType::Address],
+            &[Token::Address(owner), Token::Address(operator)],
+        );
+        let approved = decode_output(&self.call(contract, data).await?, ParamType::Bool)?;
+
+        Ok(approved.into_bool().expect("decoded as a bool"))
+    }
+
+    pub async fn erc721_owner_of(&self, contract: H160, token_id: U256) -> Result<H160> {
+        let data = calldata("ownerOf", &[ParamType::Uint(256)], &[Token::Uint(token_id)]);
+        let owner = decode_output(&self.call(contract, data).await?, ParamType::Address)?;
+
+        Ok(owner.into_address().expect("decoded as an address"))
+    }
+
+    /// The number of ERC-721 tokens of `contract` held by `owner`.
+    pub async fn erc721_balance_of(&self, contract: H160, owner: H160) -> Result<U256> {
+        let data = calldata("balanceOf", &[ParamType::Address], &[Token::Address(owner)]);
+        let balance = decode_output(&self.call(contract, data).await?, ParamType::Uint(256))?;
+
+        Ok(balance.into_uint().expect("decoded as a uint256"))
+    }
+
+    pub async fn erc1155_balance_of(&self, contract: H160, owner: H160, id: U256) -> Result<U256> {
+        let data = calldata(
+            "balanceOf",
+            &[ParamType::Address, ParamType::Uint(256)],
+            &[Token::Address(owner), Token::Uint(id)],
+        );
+        let balance = decode_output(&self.call(contract, data).await?, ParamType::Uint(256))?;
+
+        Ok(balance.into_uint().expect("decoded as a uint256"))
+    }
+
+    /// The balances of `owners[i]` in the ERC-1155 tokens `ids[i]`.
+    pub async fn erc1155_balance_of_batch(
+        &self,
+        contract: H160,
+        owners: Vec<H160>,
+        ids: Vec<U256>,
+    ) -> Result<Vec<U256>> {
+        if owners.len() != ids.len() {
+            return Err(ChainSignatureError::EvmTransaction(format!(
+                "{} owners but {} token ids",
+                owners.len(),
+                ids.len()
+            )));
+        }
+
+        let data = calldata(
+            "balanceOfBatch",
+            &[ParamType::Array(Box::new(ParamType::Address)), uint_array()],
+            &[
+                Token::Array(owners.into_iter().map(Token::Address).collect()),
+                Token::Array(ids.into_iter().map(Token::Uint).collect()),
+            ],
+        );
+        let balances = decode_output(&self.call(contract, data).await?, uint_array())?;
+
+        Ok(balances
+            .into_array()
+            .expect("decoded as an array")
+            .into_iter()
+            .map(|balance| balance.into_uint().expect("decoded as a uint256"))
+            .collect())
+    }
+}
+
+fn uint_array() -> ParamType {
+    ParamType::Array(Box::new(ParamType::Uint(256)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm::tests::{last_signed_transaction, mock_evm};
+    use ethers_core::abi::{decode, encode};
+    use ethers_providers::Http;
+    use mock_signer::evm::FakeEvmRpc;
+    use serde_json::json;
+
+    /// Decodes the arguments of the last transaction, after checking that it calls `selector` on
+    /// `contract`.
+    async fn sent_call(
+        evm_rpc: &FakeEvmRpc,
+        evm: &EVM<Http>,
+        contract: H160,
+        selector: [u8; 4],
+        inputs: &[ParamType],
+    ) -> Vec<Token> {
+        let (transaction, _) = last_signed_transaction(evm_rpc, evm, "eth").await;
+        assert_eq!(transaction.to_addr(), Some(&contract));
+        let data = transaction.data().unwrap().to_vec();
+        assert_eq!(data[..4], selector);
+        decode(inputs, &data[4..]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_nft_transfers() -> Result<()> {
+        let (_near_rpc, evm_rpc, evm) = mock_evm().await;
+        let contract = H160::repeat_byte(0x72);
+        let to = H160::repeat_byte(2);
+        let from = evm.own_address(&"eth".parse().unwrap()).await?;
+
+        evm.erc721_safe_transfer_from(contract, to, 7.into(), "eth".parse().unwrap())
+            .await?;
+        // safeTransferFrom(address,address,uint256)
+        let arguments = sent_call(
+            &evm_rpc,
+            &evm,
+            contract,
+            [0x42, 0x84, 0x2e, 0x0e],
+            &[ParamType::Address, ParamType::Address, ParamType::Uint(256)],
+        )
+        .await;
+        assert_eq!(
+            arguments,
+            vec![
+                Token::Address(from),
+                Token::Address(to),
+                Token::Uint(7.into())
+            ]
+        );
+
+        evm.erc1155_safe_transfer_from(
+            contract,
+            to,
+            1.into(),
+            10.into(),
+            Bytes::from(vec![0xaa]),
+            "eth".parse().unwrap(),
+        )
+        .await?;
+        // safeTransferFrom(address,address,uint256,uint256,bytes)
+        let arguments = sent_call(
+            &evm_rpc,
+            &evm,
+            contract,
+            [0xf2, 0x42, 0x43, 0x2a],
+            &[
+                ParamType::Address,
+                ParamType::Address,
+                ParamType::Uint(256),
+                ParamType::Uint(256),
+                ParamType::Bytes,
+            ],
+        )
+        .await;
+        assert_eq!(arguments[0], Token::Address(from));
+        assert_eq!(arguments[4], Token::Bytes(vec![0xaa]));
+
+        evm.erc1155_safe_batch_transfer_from(
+            contract,
+            to,
+            vec![1.into(), 2.into()],
+            vec![10.into(), 20.into()],
+            Bytes::default(),
+            "eth".parse().unwrap(),
+        )
+        .await?;
+        // safeBatchTransferFrom(address,address,uint256[],uint256[],bytes)
+        let arguments = sent_call(
+            &evm_rpc,
+            &evm,
+            contract,
+            [0x2e, 0xb2, 0xc2, 0xd6],
+            &[
+                ParamType::Address,
+                ParamType::Address,
+                uint_array(),
+                uint_array(),
+                ParamType::Bytes,
+            ],
+        )
+        .await;
+        assert_eq!(
+            arguments[3],
+            Token::Array(vec![Token::Uint(10.into()), Token::Uint(20.into())])
+        );
+
+        let result = evm
+            .erc1155_safe_batch_transfer_from(
+                contract,
+                to,
+                vec![1.into()],
+                vec![],
+                Bytes::default(),
+                "eth".parse().unwrap(),
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(ChainSignatureError::EvmTransaction(_))
+        ));
+
+        evm.nft_set_approval_for_all(contract, to, true, "eth".parse().unwrap())
+            .await?;
+        // setApprovalForAll(address,bool)
+        let arguments = sent_call(
+            &evm_rpc,
+            &evm,
+            contract,
+            [0xa2, 0x2c, 0xb4, 0x65],
+            &[ParamType::Address, ParamType::Bool],
+        )
+        .await;
+        assert_eq!(arguments, vec![Token::Address(to), Token::Bool(true)]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_nft_queries() -> Result<()> {
+        let (_near_rpc, evm_rpc, evm) = mock_evm().await;
+        let contract = H160::repeat_byte(0x72);
+        let (owner, operator) = (H160::repeat_byte(1), H160::repeat_byte(2));
+        let respond = |token: Token| {
+            evm_rpc.push_response("eth_call", json!(Bytes::from(encode(&[token]))));
+        };
+
+        respond(Token::Address(owner));
+        assert_eq!(evm.erc721_owner_of(contract, 7.into()).await?, owner);
+
+        respond(Token::Uint(3.into()));
+        assert_eq!(evm.erc721_balance_of(contract, owner).await?, 3.into());
+
+        respond(Token::Bool(true));
+        assert!(
+            evm.nft_is_approved_for_all(contract, owner, operator)
+                .await?
+        );
+
+        respond(Token::Uint(10.into()));
+        assert_eq!(
+            evm.erc1155_balance_of(contract, owner, 1.into()).await?,
+            10.into()
+        );
+
+        respond(Token::Array(vec![
+            Token::Uint(10.into()),
+            Token::Uint(0.into()),
+        ]));
+        assert_eq!(
+            evm.erc1155_balance_of_batch(contract, vec![owner, operator], vec![1.into(), 1.into()])
+                .await?,
+            vec![U256::from(10), U256::zero()]
+        );
+        let request = &evm_rpc.requests("eth_call")[4][0];
+        // balanceOfBatch(address[],uint256[])
+        assert_eq!(request["data"].as_str().unwrap()[..10], *"0x4e1273f4");
+
+        let result = evm
+            .erc1155_balance_of_batch(contract, vec![owner, operator], vec![1.into()])
+            .await;
+        assert!(matches!(
+            result,
+            Err(ChainSignatureError::EvmTransaction(_))
+        ));
+        // Mismatched lengths are rejected before calling the contract.
+        assert_eq!(evm_rpc.requests("eth_call").len(), 5);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use ethers_core::types::{BlockNumber, U256};
+use ethers_providers::{JsonRpcClient, Middleware, Provider};
+
+use crate::error::{ChainSignatureError, Result};
+
+const GWEI: u64 = 1_000_000_000;
+
+/// How the EIP-1559 priority fee is chosen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PriorityFee {
+    /// A fixed priority fee, in wei.
+    Fixed(U256),
+    /// The node's suggestion from `eth_maxPriorityFeePerGas`.
+    Node,
+    /// The median over the last `blocks` blocks of the `percentile` priority fee paid in each
+    /// block, from `eth_feeHistory`.
+    FeeHistory { blocks: u64, percentile: f64 },
+}
+
+/// How [`crate::evm::EVM`] prices EIP-1559 transactions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeeStrategy {
+    /// Fixed fees, in wei. Legacy transactions use `max_fee_per_gas` as their gas price.
+    Fixed {
+        max_fee_per_gas: U256,
+        max_priority_fee_per_gas: U256,
+    },
+    /// `max_fee_per_gas` is the base fee times `base_fee_multiplier` plus the priority fee. The
+    /// base fee can rise by 12.5% per block, so a multiplier of 2 keeps the transaction valid
+    /// through about six full blocks in a row.
+    Dynamic {
+        priority_fee: PriorityFee,
+        base_fee_multiplier: f64,
+    },
+}
+
+impl FeeStrategy {
+    /// A sensible strategy for the given chain.
+    ///
+    /// Ethereum and its testnets use the fee history, Arbitrum ignores priority fees and other
+    /// chains, including Polygon with its 30 gwei minimum priority fee, follow the node.
+    pub fn for_chain(chain_id: u64) -> Self {
+        let priority_fee = match chain_id {
+            // Ethereum, Sepolia, Holesky
+            1 | 11155111 | 17000 => PriorityFee::FeeHistory {
+                blocks: 10,
+                percentile: 50.0,
+            },
+            // Arbitrum One, Arbitrum Sepolia
+            42161 | 421614 => PriorityFee::Fixed(U256::zero()),
+            _ => PriorityFee::Node,
+        };
+
+        FeeStrategy::Dynamic {
+            priority_fee,
+            base_fee_multiplier: 2.0,
+        }
+    }
+
+    /// Returns `(max_fee_per_gas, max_priority_fee_per_gas)`.
+    pub async fn estimate<P: JsonRpcClient>(&self, provider: &Provider<P>) -> Result<(U256, U256)> {
+        let (priority_fee, base_fee_multiplier) = match *self {
+            FeeStrategy::Fixed {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => return Ok((max_fee_per_gas, max_priority_fee_per_gas)),
+            FeeStrategy::Dynamic {
+                priority_fee,
+                base_fee_multiplier,
+            } => (priority_fee, base_fee_multiplier),
+        };
+
+        let (base_fee, max_priority_fee_per_gas) = match priority_fee {
+            PriorityFee::Fixed(fee) => (latest_base_fee(provider).await?, fee),
+            PriorityFee::Node => (
+                latest_base_fee(provider).await?,
+                provider.request("eth_maxPriorityFeePerGas", ()).await?,
+            ),
+            PriorityFee::FeeHistory { blocks, percentile } => {
+                let history = provider
+                    .fee_history(blocks, BlockNumber::Latest, &[percentile])
+                    .await?;
+
+                let mut rewards: Vec<U256> = history
+                    .reward
+                    .iter()
+                    .filter_map(|rewards| rewards.first().copied())
+                    .collect();
+                rewards.sort();
+
+                // The last entry is the base fee of the next block, the one the transaction
+                // can be included in at the earliest.
+                let base_fee = history.base_fee_per_gas.last().copied().ok_or_else(|| {
+                    ChainSignatureError::EvmTransaction("empty fee history".to_string())
+                })?;
+                let priority_fee = rewards
+                    .get(rewards.len() / 2)
+                    .copied()
+                    .unwrap_or_else(|| U256::from(GWEI));
+
+                (base_fee, priority_fee)
+            }
+        };
+
+        let max_fee_per_gas = multiply(base_fee, base_fee_multiplier) + max_priority_fee_per_gas;
+
+        Ok((max_fee_per_gas, max_priority_fee_per_gas))
+    }
+}
+
+/// The lowest fee a replacement transaction can pay, 10% above `fee`. Nodes reject a
+/// transaction with the nonce of a pending one unless it raises every fee by at least that much.
+pub fn replacement_fee(fee: U256) -> U256 {
+    fee + (fee + 9) / 10
+}
+
+async fn latest_base_fee<P: JsonRpcClient>(provider: &Provider<P>) -> Result<U256> {
+    let latest_block = provider
+        .get_block(BlockNumber::Latest)
+        .await?
+        .ok_or_else(|| ChainSignatureError::EvmTransaction("latest block not found".to_string()))?;
+
+    Ok(latest_block.base_fee_per_gas.unwrap_or_default())
+}
+
+/// `value * multiplier`, to a thousandth of the multiplier.
+fn multiply(value: U256, multiplier: f64) -> U256 {
+    value * U256::from((multiplier.max(0.0) * 1000.0).round() as u64) / 1000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::types::{Block, H256};
+    use ethers_providers::Http;
+    use mock_signer::evm::FakeEvmRpc;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_fee_strategies() -> Result<()> {
+        let evm_rpc = FakeEvmRpc::start().await;
+        let provider = Provider::<Http>::try_from(evm_rpc.url()).unwrap();
+
+        let latest_block = Block::<H256> {
+            base_fee_per_gas: Some(U256::from(100)),
+            ..Default::default()
+        };
+        evm_rpc.set_response("eth_getBlockByNumber", json!(latest_block));
+        evm_rpc.set_response("eth_maxPriorityFeePerGas", json!("0x7"));
+        evm_rpc.set_response(
+            "eth_feeHistory",
+            json!({
+                "oldestBlock": "0x10",
+                "baseFeePerGas": ["0x64", "0x6e", "0x78", "0x82"],
+                "gasUsedRatio": [0.5, 1.0, 0.9],
+                "reward": [["0x5"], ["0x1"], ["0x9"]],
+            }),
+        );
+
+        let fixed = FeeStrategy::Fixed {
+            max_fee_per_gas: 50.into(),
+            max_priority_fee_per_gas: 2.into(),
+        };
+        assert_eq!(fixed.estimate(&provider).await?, (50.into(), 2.into()));
+
+        let node = FeeStrategy::Dynamic {
+            priority_fee: PriorityFee::Node,
+            base_fee_multiplier: 1.5,
+        };
+        assert_eq!(node.estimate(&provider).await?, (157.into(), 7.into()));
+
+        let fee_history = FeeStrategy::Dynamic {
+            priority_fee: PriorityFee::FeeHistory {
+                blocks: 3,
+                percentile: 50.0,
+            },
+            base_fee_multiplier: 2.0,
+        };
+        // Median reward 5 on top of twice the next block's base fee of 130.
+        assert_eq!(
+            fee_history.estimate(&provider).await?,
+            (265.into(), 5.into())
+        );
+        assert_eq!(
+            evm_rpc.requests("eth_feeHistory"),
+            vec![json!(["0x3", "latest", [50.0]])]
+        );
+
+        assert_eq!(
+            FeeStrategy::for_chain(42161).estimate(&provider).await?,
+            (200.into(), 0.into())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_replacement_fee() {
+        assert_eq!(replacement_fee(100.into()), 110.into());
+        assert_eq!(replacement_fee(101.into()), 112.into());
+        assert_eq!(replacement_fee(0.into()), 0.into());
+    }
+}
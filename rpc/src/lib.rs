@@ -1,3 +1,9 @@
 pub mod api;
+pub mod btc;
+pub mod error;
 pub mod evm;
+pub mod fee;
+pub mod nonce;
+pub mod receipt;
 pub mod rpc;
+pub mod sign;
@@ -0,0 +1,361 @@
+use std::collections::{hash_map::Entry, BTreeSet, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use ethers_core::types::{BlockNumber, H160, U256};
+use ethers_providers::{JsonRpcClient, Middleware, Provider};
+use near_crypto::PublicKey;
+use near_sdk::AccountId;
+use tokio::sync::Mutex;
+
+use utils::signer::NearSigner;
+
+use crate::api::{get_current_nonce, NearRpcClient};
+use crate::error::Result;
+
+/// The last nonce reserved for an access key, if any.
+type KeyNonce = Arc<Mutex<Option<u64>>>;
+
+/// Hands out access key nonces to concurrent transactions.
+///
+/// The first reservation for an access key reads its nonce from the chain and later ones are
+/// served from memory, so parallel calls get distinct, increasing nonces instead of all using
+/// the on-chain nonce plus one. Every access key has its own lock, so reading the nonce of one
+/// key from the chain doesn't hold up reservations for the others. Clones share their state, so
+/// a single manager can be shared by every client signing with the same keys.
+#[derive(Clone, Default)]
+pub struct NonceManager {
+    nonces: Arc<Mutex<HashMap<(AccountId, PublicKey), KeyNonce>>>,
+    next_access_key: Arc<AtomicUsize>,
+}
+
+impl NonceManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reserves the next nonce for the access key of `signer`.
+    pub async fn reserve(&self, client: &NearRpcClient, signer: &dyn NearSigner) -> Result<u64> {
+        let key_nonce = self.key_nonce(signer).await;
+        let mut key_nonce = key_nonce.lock().await;
+
+        let nonce = match *key_nonce {
+            Some(nonce) => nonce + 1,
+            None => get_current_nonce(client, signer).await? + 1,
+        };
+        *key_nonce = Some(nonce);
+
+        Ok(nonce)
+    }
+
+    /// Moves the tracked nonce of `signer` forward to `ak_nonce`, the access key nonce reported
+    /// by an `InvalidNonce` error, so that the next reservation is accepted.
+    pub async fn resync(&self, signer: &dyn NearSigner, ak_nonce: u64) {
+        let key_nonce = self.key_nonce(signer).await;
+        let mut key_nonce = key_nonce.lock().await;
+        *key_nonce = Some(key_nonce.unwrap_or_default().max(ak_nonce));
+    }
+
+    /// Forgets the tracked nonce of `signer`, so the next reservation reads it from the chain.
+    pub async fn reset(&self, signer: &dyn NearSigner) {
+        *self.key_nonce(signer).await.lock().await = None;
+    }
+
+    /// The tracked nonce of the access key of `signer`, behind the lock of that key alone.
+    async fn key_nonce(&self, signer: &dyn NearSigner) -> KeyNonce {
+        self.nonces
+            .lock()
+            .await
+            .entry((signer.account_id().clone(), signer.public_key().clone()))
+            .or_default()
+            .clone()
+    }
+
+    /// Picks the access key for the next transaction, rotating through `access_keys` so that
+    /// concurrent transactions are spread over several keys of the same account.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `access_keys` is empty.
+    pub fn next_access_key<'a>(
+        &self,
+        access_keys: &'a [Arc<dyn NearSigner>],
+    ) -> &'a dyn NearSigner {
+        let index = self.next_access_key.fetch_add(1, Ordering::Relaxed);
+        access_keys[index % access_keys.len()].as_ref()
+    }
+}
+
+/// Hands out nonces for EVM transactions sent from derived addresses.
+///
+/// The first reservation for an address reads its `pending` transaction count and later ones
+/// are served from memory, so transactions sent back to back or concurrently get consecutive
+/// nonces before the earlier ones are mined. Every reservation has to be settled with
+/// [`Self::confirm`], [`Self::release`] or [`Self::invalidate`]. Clones share their state.
+#[derive(Clone, Default)]
+pub struct EvmNonceManager {
+    nonces: Arc<Mutex<HashMap<(u64, H160), AddressNonces>>>,
+}
+
+struct AddressNonces {
+    /// The nonce of the next reservation.
+    next: U256,
+    /// Reserved nonces that are not settled yet.
+    outstanding: BTreeSet<U256>,
+    /// Whether `next` may be off, because a nonce below it went unused or turned out to be
+    /// taken. The nonces are read from the chain again once none are outstanding.
+    stale: bool,
+}
+
+impl EvmNonceManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reserves the next nonce for `address` on the chain `chain_id`.
+    pub async fn reserve<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        chain_id: u64,
+        address: H160,
+    ) -> Result<U256> {
+        let mut nonces = self.nonces.lock().await;
+
+        let address_nonces = match nonces.entry((chain_id, address)) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let next = provider
+                    .get_transaction_count(address, Some(BlockNumber::Pending.into()))
+                    .await?;
+                entry.insert(AddressNonces {
+                    next,
+                    outstanding: BTreeSet::new(),
+                    stale: false,
+                })
+            }
+        };
+
+        let nonce = address_nonces.next;
+        address_nonces.next += U256::one();
+        address_nonces.outstanding.insert(nonce);
+
+        Ok(nonce)
+    }
+
+    /// Settles `nonce`, which was used by a transaction the node accepted.
+    pub async fn confirm(&self, chain_id: u64, address: H160, nonce: U256) {
+        self.settle(chain_id, address, nonce, |_| false).await;
+    }
+
+    /// Gives back `nonce`, which won't be used. The latest reservation is handed out again,
+    /// while an earlier one leaves a gap that is closed by reading the nonces from the chain
+    /// once no other reservation is outstanding.
+    pub async fn release(&self, chain_id: u64, address: H160, nonce: U256) {
+        self.settle(chain_id, address, nonce, |address_nonces| {
+            if address_nonces.next == nonce + 1 {
+                address_nonces.next = nonce;
+                false
+            } else {
+                true
+            }
+        })
+        .await;
+    }
+
+    /// Settles `nonce`, which the node reported as already taken or which may or may not have
+    /// been used. The nonces are read from the chain again once no other reservation is
+    /// outstanding, so transactions still being signed keep theirs.
+    pub async fn invalidate(&self, chain_id: u64, address: H160, nonce: U256) {
+        self.settle(chain_id, address, nonce, |_| true).await;
+    }
+
+    /// Removes `nonce` from the outstanding reservations of `address` and forgets its nonces
+    /// if `makes_stale` reports them as off and none are outstanding anymore.
+    async fn settle(
+        &self,
+        chain_id: u64,
+        address: H160,
+        nonce: U256,
+        makes_stale: impl FnOnce(&mut AddressNonces) -> bool,
+    ) {
+        let mut nonces = self.nonces.lock().await;
+        let Entry::Occupied(mut entry) = nonces.entry((chain_id, address)) else {
+            return;
+        };
+
+        let address_nonces = entry.get_mut();
+        if !address_nonces.outstanding.remove(&nonce) {
+            return;
+        }
+        if makes_stale(address_nonces) {
+            address_nonces.stale = true;
+        }
+        if address_nonces.stale && address_nonces.outstanding.is_empty() {
+            entry.remove();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_providers::Http;
+    use mock_signer::{evm::FakeEvmRpc, server::FakeNearRpc, MockSigner};
+    use near_crypto::{InMemorySigner, KeyType};
+    use serde_json::json;
+    use tokio::task::JoinSet;
+
+    #[tokio::test]
+    async fn test_concurrent_reservations_are_unique() -> Result<()> {
+        let server =
+            FakeNearRpc::start(MockSigner::default(), "signer.test.near".parse().unwrap()).await;
+        let client = NearRpcClient::from(server.client());
+        let signer =
+            InMemorySigner::from_seed("alice.test.near".parse().unwrap(), KeyType::ED25519, "test");
+        server.set_access_key_nonce(signer.account_id.clone(), signer.public_key.clone(), 10);
+
+        let nonce_manager = NonceManager::new();
+        let mut reservations = JoinSet::new();
+        for _ in 0..20 {
+            let (nonce_manager, client, signer) =
+                (nonce_manager.clone(), client.clone(), signer.clone());
+            reservations.spawn(async move { nonce_manager.reserve(&client, &signer).await });
+        }
+
+        let mut nonces = vec![];
+        while let Some(nonce) = reservations.join_next().await {
+            nonces.push(nonce.unwrap()?);
+        }
+        nonces.sort();
+        assert_eq!(nonces, (11..=30).collect::<Vec<_>>());
+
+        nonce_manager.resync(&signer, 50).await;
+        assert_eq!(nonce_manager.reserve(&client, &signer).await?, 51);
+
+        nonce_manager.reset(&signer).await;
+        assert_eq!(nonce_manager.reserve(&client, &signer).await?, 11);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reservations_only_wait_for_their_own_key() -> Result<()> {
+        let server =
+            FakeNearRpc::start(MockSigner::default(), "signer.test.near".parse().unwrap()).await;
+        let client = NearRpcClient::from(server.client());
+        let [busy, idle] = ["busy", "idle"].map(|seed| {
+            InMemorySigner::from_seed("alice.test.near".parse().unwrap(), KeyType::ED25519, seed)
+        });
+        server.set_access_key_nonce(idle.account_id.clone(), idle.public_key.clone(), 3);
+
+        let nonce_manager = NonceManager::new();
+        // Stands in for a reservation of `busy` that is still reading its nonce from the chain.
+        let busy_nonce = nonce_manager.key_nonce(&busy).await;
+        let _busy_lock = busy_nonce.lock().await;
+
+        let nonce = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            nonce_manager.reserve(&client, &idle),
+        )
+        .await
+        .expect("blocked by the reservation of another key")?;
+        assert_eq!(nonce, 4);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_evm_nonces_start_from_pending() -> Result<()> {
+        let evm_rpc = FakeEvmRpc::start().await;
+        let provider = Provider::<Http>::try_from(evm_rpc.url()).unwrap();
+        evm_rpc.set_response("eth_getTransactionCount", json!("0x5"));
+        let (first, second) = (H160::repeat_byte(1), H160::repeat_byte(2));
+
+        let nonce_manager = EvmNonceManager::new();
+        let reservations = futures::future::try_join_all(
+            (0..5).map(|_| nonce_manager.reserve(&provider, 1, first)),
+        )
+        .await?;
+        let mut nonces: Vec<u64> = reservations.iter().map(U256::as_u64).collect();
+        nonces.sort();
+        assert_eq!(nonces, vec![5, 6, 7, 8, 9]);
+        assert_eq!(
+            evm_rpc.requests("eth_getTransactionCount"),
+            vec![json!([first, "pending"])]
+        );
+
+        // Addresses and chains are tracked separately.
+        assert_eq!(nonce_manager.reserve(&provider, 1, second).await?, 5.into());
+        assert_eq!(nonce_manager.reserve(&provider, 2, first).await?, 5.into());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_evm_nonces_resync_once_settled() -> Result<()> {
+        let evm_rpc = FakeEvmRpc::start().await;
+        let provider = Provider::<Http>::try_from(evm_rpc.url()).unwrap();
+        evm_rpc.set_response("eth_getTransactionCount", json!("0x5"));
+        let address = H160::repeat_byte(1);
+        let nonce_manager = EvmNonceManager::new();
+        let reserve = || nonce_manager.reserve(&provider, 1, address);
+
+        let (five, six, seven) = (reserve().await?, reserve().await?, reserve().await?);
+        assert_eq!((five, six, seven), (5.into(), 6.into(), 7.into()));
+
+        // The latest nonce is handed out again.
+        nonce_manager.release(1, address, seven).await;
+        let seven = reserve().await?;
+        assert_eq!(seven, 7.into());
+
+        // Another client took nonce 5, but 6 and 7 are still being signed and keep theirs.
+        evm_rpc.set_response("eth_getTransactionCount", json!("0xa"));
+        nonce_manager.invalidate(1, address, five).await;
+        let eight = reserve().await?;
+        assert_eq!(eight, 8.into());
+        assert_eq!(evm_rpc.requests("eth_getTransactionCount").len(), 1);
+
+        // A released nonce below the latest leaves a gap until every reservation is settled.
+        nonce_manager.release(1, address, six).await;
+        nonce_manager.confirm(1, address, seven).await;
+        nonce_manager.confirm(1, address, eight).await;
+        assert_eq!(reserve().await?, 10.into());
+        assert_eq!(evm_rpc.requests("eth_getTransactionCount").len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_access_keys_rotate() {
+        let access_keys: Vec<Arc<dyn NearSigner>> = ["first", "second"]
+            .iter()
+            .map(|seed| {
+                Arc::new(InMemorySigner::from_seed(
+                    "alice.test.near".parse().unwrap(),
+                    KeyType::ED25519,
+                    seed,
+                )) as Arc<dyn NearSigner>
+            })
+            .collect();
+
+        let nonce_manager = NonceManager::new();
+        let picked: Vec<_> = (0..4)
+            .map(|_| {
+                nonce_manager
+                    .next_access_key(&access_keys)
+                    .public_key()
+                    .clone()
+            })
+            .collect();
+
+        assert_eq!(
+            picked,
+            vec![
+                access_keys[0].public_key().clone(),
+                access_keys[1].public_key().clone(),
+                access_keys[0].public_key().clone(),
+                access_keys[1].public_key().clone(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,332 @@
+use std::time::Duration;
+
+use ethers_core::abi::{decode, ParamType, Token};
+use ethers_core::types::{
+    transaction::eip2718::TypedTransaction, BlockNumber, TransactionReceipt, H256, U256,
+};
+use ethers_core::utils::hex;
+use ethers_providers::{JsonRpcClient, Middleware, Provider, ProviderError, RpcError};
+use tokio::time;
+
+use crate::error::{ChainSignatureError, Result};
+
+/// Selector of `Error(string)`, the revert data of `require` and `revert` with a message.
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+/// Selector of `Panic(uint256)`, the revert data of failed assertions and arithmetic errors.
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// How long to wait for an EVM transaction and how deep it must be buried before its receipt is
+/// returned. Polls at the interval of the provider.
+#[derive(Debug, Clone)]
+pub struct ConfirmationPolicy {
+    /// Blocks including the one the transaction is in, so 1 returns as soon as it is mined.
+    pub confirmations: u64,
+    pub timeout: Duration,
+}
+
+impl Default for ConfirmationPolicy {
+    fn default() -> Self {
+        Self {
+            confirmations: 1,
+            timeout: Duration::from_secs(300),
+        }
+    }
+}
+
+impl ConfirmationPolicy {
+    pub fn with_confirmations(mut self, confirmations: u64) -> Self {
+        self.confirmations = confirmations.max(1);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// Waits until `transaction`, sent as `tx_hash`, has `policy.confirmations` confirmations and
+/// returns its receipt.
+///
+/// Fails with [`ChainSignatureError::EvmReverted`] if it reverted, with
+/// [`ChainSignatureError::EvmTransactionReplaced`] if another transaction with the same nonce
+/// was mined instead and with [`ChainSignatureError::EvmTransactionDropped`] if the node forgot
+/// about it. A receipt that disappears in a reorg is waited for again.
+pub async fn wait_for_receipt<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    transaction: &TypedTransaction,
+    tx_hash: H256,
+    policy: &ConfirmationPolicy,
+) -> Result<TransactionReceipt> {
+    let from = *transaction.from().ok_or_else(|| {
+        ChainSignatureError::EvmTransaction("transaction has no sender".to_string())
+    })?;
+    let nonce = *transaction.nonce().ok_or_else(|| {
+        ChainSignatureError::EvmTransaction("transaction has no nonce".to_string())
+    })?;
+
+    let wait = async {
+        let mut seen = false;
+        loop {
+            if let Some(receipt) = confirmed_receipt(provider, tx_hash, policy).await? {
+                if receipt.status != Some(1.into()) {
+                    return Err(ChainSignatureError::EvmReverted {
+                        tx_hash,
+                        reason: replay_revert_reason(provider, transaction, &receipt).await,
+                        receipt: Box::new(receipt),
+                    });
+                }
+                return Ok(receipt);
+            }
+
+            if provider.get_transaction(tx_hash).await?.is_some() {
+                seen = true;
+            } else {
+                let mined_nonce = provider
+                    .get_transaction_count(from, Some(BlockNumber::Latest.into()))
+                    .await?;
+                // The transaction may have been mined between the receipt and transaction
+                // lookups, so only a nonce used without a receipt means it was replaced.
+                if mined_nonce > nonce && provider.get_transaction_receipt(tx_hash).await?.is_none()
+                {
+                    return Err(ChainSignatureError::EvmTransactionReplaced { tx_hash, nonce });
+                }
+                // Load balanced providers may not know about a transaction that was just sent,
+                // so it is only dropped once it has been seen.
+                if seen && mined_nonce <= nonce {
+                    return Err(ChainSignatureError::EvmTransactionDropped(tx_hash));
+                }
+            }
+
+            time::sleep(provider.get_interval()).await;
+        }
+    };
+
+    time::timeout(policy.timeout, wait)
+        .await
+        .map_err(|_| ChainSignatureError::Timeout(policy.timeout))?
+}
+
+async fn confirmed_receipt<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    tx_hash: H256,
+    policy: &ConfirmationPolicy,
+) -> Result<Option<TransactionReceipt>> {
+    let Some(receipt) = provider.get_transaction_receipt(tx_hash).await? else {
+        return Ok(None);
+    };
+    let Some(block_number) = receipt.block_number else {
+        return Ok(None);
+    };
+
+    let latest_block = provider.get_block_number().await?;
+    if latest_block + 1 < block_number + policy.confirmations {
+        return Ok(None);
+    }
+
+    Ok(Some(receipt))
+}
+
+/// Replays `transaction` with `eth_call` to recover the revert data, which receipts don't
+/// include. The call runs on the state before the block it reverted in, since the state after
+/// it already has the transaction's nonce used and the effects of the rest of the block.
+async fn replay_revert_reason<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    transaction: &TypedTransaction,
+    receipt: &TransactionReceipt,
+) -> Option<String> {
+    let block = receipt
+        .block_number
+        .map(|number| number.saturating_sub(1.into()).into());
+    match provider.call(transaction, block).await {
+        Ok(_) => None,
+        Err(err) => revert_data(&err).and_then(|data| decode_revert_reason(&data)),
+    }
+}
+
+/// The revert data of a failed `eth_call` or `eth_estimateGas`, if it failed because the
+/// execution reverted.
+pub fn revert_data(err: &ProviderError) -> Option<Vec<u8>> {
+    err.as_error_response()?
+        .as_revert_data()
+        .map(|data| data.to_vec())
+}
+
+/// Decodes revert data into a readable reason: the message of `Error(string)`, the code of
+/// `Panic(uint256)` or, for custom errors, the raw data. Returns `None` for empty revert data.
+pub fn decode_revert_reason(data: &[u8]) -> Option<String> {
+    if data.is_empty() {
+        return None;
+    }
+
+    let (selector, arguments) = data.split_at(data.len().min(4));
+    match selector {
+        s if s == ERROR_SELECTOR => {
+            if let Ok(tokens) = decode(&[ParamType::String], arguments) {
+                if let [Token::String(message)] = tokens.as_slice() {
+                    return Some(message.clone());
+                }
+            }
+        }
+        s if s == PANIC_SELECTOR => {
+            if let Ok(tokens) = decode(&[ParamType::Uint(256)], arguments) {
+                if let [Token::Uint(code)] = tokens.as_slice() {
+                    return Some(format!(
+                        "panic {:#04x} ({})",
+                        code,
+                        panic_description(*code)
+                    ));
+                }
+            }
+        }
+        _ => {}
+    }
+
+    Some(format!("custom error 0x{}", hex::encode(data)))
+}
+
+fn panic_description(code: U256) -> &'static str {
+    if code > U256::from(u8::MAX) {
+        return "unknown panic";
+    }
+    match code.as_u32() {
+        0x01 => "assertion failed",
+        0x11 => "arithmetic overflow or underflow",
+        0x12 => "division or modulo by zero",
+        0x21 => "invalid enum value",
+        0x22 => "invalid storage byte array",
+        0x31 => "pop on an empty array",
+        0x32 => "array index out of bounds",
+        0x41 => "out of memory",
+        0x51 => "call to an uninitialized function",
+        _ => "unknown panic",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::abi::encode;
+    use ethers_core::types::{Bytes, Transaction, TransactionRequest, H160};
+    use ethers_providers::Http;
+    use mock_signer::evm::FakeEvmRpc;
+    use serde_json::json;
+
+    fn encode_revert(selector: [u8; 4], token: Token) -> Vec<u8> {
+        [&selector[..], &encode(&[token])].concat()
+    }
+
+    #[test]
+    fn test_decode_revert_reason() {
+        assert_eq!(
+            decode_revert_reason(&encode_revert(
+                ERROR_SELECTOR,
+                Token::String("insufficient balance".to_string())
+            )),
+            Some("insufficient balance".to_string())
+        );
+        assert_eq!(
+            decode_revert_reason(&encode_revert(PANIC_SELECTOR, Token::Uint(0x11.into()))),
+            Some("panic 0x11 (arithmetic overflow or underflow)".to_string())
+        );
+        assert_eq!(
+            decode_revert_reason(&[0xde, 0xad, 0xbe, 0xef]),
+            Some("custom error 0xdeadbeef".to_string())
+        );
+        assert_eq!(decode_revert_reason(&[]), None);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_receipt() -> Result<()> {
+        let evm_rpc = FakeEvmRpc::start().await;
+        let provider = Provider::<Http>::try_from(evm_rpc.url())
+            .unwrap()
+            .interval(Duration::from_millis(10));
+        let tx_hash = H256::repeat_byte(0xab);
+        let transaction = TypedTransaction::Legacy(
+            TransactionRequest::new()
+                .from(H160::repeat_byte(1))
+                .to(H160::repeat_byte(2))
+                .nonce(5),
+        );
+        let pending = json!(Transaction {
+            hash: tx_hash,
+            ..Default::default()
+        });
+        let receipt = |status: u64| {
+            json!(TransactionReceipt {
+                transaction_hash: tx_hash,
+                block_number: Some(10.into()),
+                status: Some(status.into()),
+                ..Default::default()
+            })
+        };
+        let policy = ConfirmationPolicy::default()
+            .with_confirmations(3)
+            .with_timeout(Duration::from_secs(5));
+
+        // Mined in block 10 and returned once block 12 is out.
+        evm_rpc.push_response("eth_getTransactionReceipt", json!(null));
+        evm_rpc.set_response("eth_getTransactionReceipt", receipt(1));
+        evm_rpc.set_response("eth_getTransactionByHash", pending.clone());
+        evm_rpc.push_response("eth_blockNumber", json!("0xa"));
+        evm_rpc.push_response("eth_blockNumber", json!("0xb"));
+        evm_rpc.set_response("eth_blockNumber", json!("0xc"));
+        let mined = wait_for_receipt(&provider, &transaction, tx_hash, &policy).await?;
+        assert_eq!(mined.block_number, Some(10.into()));
+        assert_eq!(evm_rpc.requests("eth_blockNumber").len(), 3);
+
+        // Reverted, with the reason recovered by replaying the call on top of block 9.
+        evm_rpc.set_response("eth_getTransactionReceipt", receipt(0));
+        evm_rpc.set_error(
+            "eth_call",
+            3,
+            "execution reverted: insufficient balance",
+            Some(json!(Bytes::from(encode_revert(
+                ERROR_SELECTOR,
+                Token::String("insufficient balance".to_string())
+            )))),
+        );
+        let result = wait_for_receipt(&provider, &transaction, tx_hash, &policy).await;
+        assert!(matches!(
+            result,
+            Err(ChainSignatureError::EvmReverted { reason: Some(ref reason), .. })
+                if reason == "insufficient balance"
+        ));
+        assert_eq!(
+            evm_rpc.requests("eth_call").last().unwrap()[1],
+            json!("0x9")
+        );
+
+        // Gone from the mempool while another transaction used its nonce.
+        evm_rpc.set_response("eth_getTransactionReceipt", json!(null));
+        evm_rpc.set_response("eth_getTransactionByHash", json!(null));
+        evm_rpc.set_response("eth_getTransactionCount", json!("0x6"));
+        let result = wait_for_receipt(&provider, &transaction, tx_hash, &policy).await;
+        assert!(matches!(
+            result,
+            Err(ChainSignatureError::EvmTransactionReplaced { nonce, .. }) if nonce == 5.into()
+        ));
+
+        // Gone from the mempool after being seen, with its nonce still unused.
+        evm_rpc.set_response("eth_getTransactionCount", json!("0x5"));
+        evm_rpc.push_response("eth_getTransactionByHash", pending);
+        let result = wait_for_receipt(&provider, &transaction, tx_hash, &policy).await;
+        assert!(matches!(
+            result,
+            Err(ChainSignatureError::EvmTransactionDropped(hash)) if hash == tx_hash
+        ));
+
+        // Never seen, so it could still be on its way to the node.
+        let result = wait_for_receipt(
+            &provider,
+            &transaction,
+            tx_hash,
+            &policy.with_timeout(Duration::from_millis(100)),
+        )
+        .await;
+        assert!(matches!(result, Err(ChainSignatureError::Timeout(_))));
+
+        Ok(())
+    }
+}
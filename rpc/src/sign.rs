@@ -0,0 +1,172 @@
+//! The NEAR side of the chain clients: which signer contract and key version sign their
+//! transactions, and how the `sign` transactions are sent, paid for and awaited.
+
+use std::sync::Arc;
+
+use k256::ecdsa::VerifyingKey;
+use near_sdk::AccountId;
+use utils::{
+    kdf::{derive_child_public_key_for_version, RootPublicKeys},
+    signer::NearSigner,
+    types::{DerivationPath, KeyVersion, NearAuthentication, SignRequest},
+};
+
+use crate::{
+    api::{get_near_client, NearRpcClient, WaitPolicy},
+    error::Result,
+    nonce::NonceManager,
+    rpc::{call_sign, call_sign_batch, get_root_public_key, SignFee, SignOutcome},
+};
+
+/// How a chain client requests signatures from the signer contract, shared by
+/// [`crate::btc::BTC`] and [`crate::evm::EVM`] and changed through [`SignConfigBuilder`].
+pub struct SignConfig {
+    near_authentication: NearAuthentication,
+    near_client: NearRpcClient,
+    contract: AccountId,
+    pub(crate) key_version: KeyVersion,
+    root_public_keys: RootPublicKeys,
+    access_keys: Vec<Arc<dyn NearSigner>>,
+    nonce_manager: NonceManager,
+    wait_policy: WaitPolicy,
+    sign_fee: SignFee,
+}
+
+impl SignConfig {
+    /// Signs with the access key of `near_authentication` and the root key of `key_version` of
+    /// the signer contract `contract`.
+    pub fn new(
+        near_authentication: NearAuthentication,
+        contract: AccountId,
+        key_version: KeyVersion,
+    ) -> Result<Self> {
+        Ok(Self {
+            near_client: get_near_client(near_authentication.network.clone())?,
+            access_keys: vec![near_authentication.signer.clone()],
+            near_authentication,
+            contract,
+            key_version,
+            root_public_keys: RootPublicKeys::new(),
+            nonce_manager: NonceManager::new(),
+            wait_policy: WaitPolicy::default(),
+            sign_fee: SignFee::default(),
+        })
+    }
+
+    /// The NEAR account requesting the signatures, whose derived keys sign.
+    pub(crate) fn account_id(&self) -> &AccountId {
+        self.near_authentication.account_id()
+    }
+
+    /// The public key derived for `signer_id` and `path` from the root key of the configured
+    /// key version.
+    pub(crate) async fn derive_public_key(
+        &self,
+        signer_id: &str,
+        path: &DerivationPath,
+    ) -> Result<VerifyingKey> {
+        let root_public_key = get_root_public_key(
+            &self.near_client,
+            self.contract.clone(),
+            &self.root_public_keys,
+            self.key_version,
+        )
+        .await?;
+
+        Ok(derive_child_public_key_for_version(
+            &root_public_key,
+            self.key_version,
+            signer_id.to_string(),
+            path.clone(),
+        )?)
+    }
+
+    /// Has `payload` signed with the key derived for `path`.
+    pub(crate) async fn sign(
+        &self,
+        payload: [u8; 32],
+        path: DerivationPath,
+    ) -> Result<SignOutcome> {
+        call_sign(
+            &self.near_client,
+            self.contract.clone(),
+            self.sign_request(payload, path),
+            self.nonce_manager.next_access_key(&self.access_keys),
+            &self.nonce_manager,
+            &self.wait_policy,
+            &self.sign_fee,
+        )
+        .await
+    }
+
+    /// Has every payload signed with the key derived for `path`, see [`call_sign_batch`].
+    pub(crate) async fn sign_batch(
+        &self,
+        payloads: &[[u8; 32]],
+        path: DerivationPath,
+    ) -> Vec<Result<SignOutcome>> {
+        let sign_requests = payloads
+            .iter()
+            .map(|payload| self.sign_request(*payload, path.clone()))
+            .collect();
+
+        call_sign_batch(
+            &self.near_client,
+            self.contract.clone(),
+            sign_requests,
+            self.nonce_manager.next_access_key(&self.access_keys),
+            &self.nonce_manager,
+            &self.wait_policy,
+            &self.sign_fee,
+        )
+        .await
+    }
+
+    fn sign_request(&self, payload: [u8; 32], path: DerivationPath) -> SignRequest {
+        SignRequest {
+            payload,
+            path,
+            key_version: self.key_version.into(),
+        }
+    }
+}
+
+/// Builders for the [`SignConfig`] of a chain client.
+pub trait SignConfigBuilder: Sized {
+    /// The configuration changed by the builders.
+    fn sign_config_mut(&mut self) -> &mut SignConfig;
+
+    /// Pins the root key used for `key_version` instead of querying the contract, which only
+    /// exposes the key of the current version.
+    fn with_root_public_key(mut self, key_version: KeyVersion, public_key: VerifyingKey) -> Self {
+        self.sign_config_mut()
+            .root_public_keys
+            .insert(key_version, public_key);
+        self
+    }
+
+    /// Adds access keys of the same account to rotate through, so that concurrent signature
+    /// requests are spread over several keys instead of queueing on one key's nonce.
+    fn with_access_keys(mut self, access_keys: Vec<Arc<dyn NearSigner>>) -> Self {
+        self.sign_config_mut().access_keys.extend(access_keys);
+        self
+    }
+
+    /// Shares nonce tracking with other clients that sign with the same access keys.
+    fn with_nonce_manager(mut self, nonce_manager: NonceManager) -> Self {
+        self.sign_config_mut().nonce_manager = nonce_manager;
+        self
+    }
+
+    /// Sets how long and how often to poll for the outcome of sign transactions.
+    fn with_wait_policy(mut self, wait_policy: WaitPolicy) -> Self {
+        self.sign_config_mut().wait_policy = wait_policy;
+        self
+    }
+
+    /// Sets the gas and the deposit limit of sign transactions.
+    fn with_sign_fee(mut self, sign_fee: SignFee) -> Self {
+        self.sign_config_mut().sign_fee = sign_fee;
+        self
+    }
+}
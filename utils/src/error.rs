@@ -0,0 +1,52 @@
+use thiserror::Error;
+
+/// Errors returned when parsing keys or deriving child keys and addresses.
+#[derive(Debug, Error)]
+pub enum KdfError {
+    #[error("unsupported key type `{0}`, expected secp256k1")]
+    UnsupportedKeyType(String),
+    #[error("invalid base58 key encoding: {0}")]
+    InvalidBase58(String),
+    #[error("invalid hex key encoding: {0}")]
+    InvalidHex(String),
+    #[error("invalid secp256k1 key length {0}, expected 33, 64 or 65 bytes")]
+    InvalidKeyLength(usize),
+    #[error("invalid secp256k1 public key: {0}")]
+    InvalidPublicKey(#[from] k256::ecdsa::Error),
+    #[error("unsupported MPC key version {0}")]
+    UnsupportedKeyVersion(u32),
+    #[error("no root public key registered for MPC key version {0}")]
+    MissingRootKey(u32),
+    #[error("invalid derivation path: {0}")]
+    InvalidDerivationPath(String),
+    #[error("failed to encode address: {0}")]
+    AddressEncoding(String),
+}
+
+/// Errors returned when checking or converting a `SignatureResponse`.
+#[derive(Debug, Error)]
+pub enum SignatureError {
+    #[error("malformed signature: {0}")]
+    Malformed(#[from] k256::ecdsa::Error),
+    #[error("recovery id {actual} does not match big_r, expected {expected}")]
+    RecoveryIdMismatch { expected: u8, actual: u8 },
+    #[error("signature has a high S value and is malleable")]
+    HighS,
+    #[error("signature does not verify against the expected public key")]
+    VerificationFailed,
+}
+
+/// Errors returned by `NearSigner` implementations.
+#[derive(Debug, Error)]
+pub enum SignerError {
+    #[error("failed to read key file: {0}")]
+    KeyFile(String),
+    #[error("environment variable {0} is not set")]
+    MissingEnvVar(String),
+    #[error("invalid key: {0}")]
+    InvalidKey(String),
+    #[error("signature or secret key does not match public key {0}")]
+    KeyMismatch(String),
+    #[error("remote signer failed: {0}")]
+    Remote(String),
+}
@@ -1,16 +1,30 @@
-use k256::ecdsa::{Error, VerifyingKey};
-use near_sdk::bs58;
+use std::collections::BTreeMap;
 
+use k256::ecdsa::VerifyingKey;
+use near_crypto::KeyType;
+use near_sdk::{bs58, CurveType};
+
+use bech32::{hrp, segwit, Hrp};
 use ethers_core::{
-    k256::{sha2::Digest, AffinePoint, Scalar},
+    k256::{
+        elliptic_curve::{point::AffineCoordinates, PrimeField},
+        sha2::{Digest, Sha256},
+        AffinePoint, ProjectivePoint, Scalar,
+    },
     utils::{hex, keccak256},
 };
+use ripemd::Ripemd160;
 use sha3::Sha3_256;
 
-use crate::types::ScalarExt;
+use crate::error::KdfError;
+use crate::types::{BitcoinAddressType, BitcoinNetwork, DerivationPath, KeyVersion, ScalarExt};
 
 /// Converts a NEAR Account JSON (NAJ) public key to a VerifyingKey.
 ///
+/// Accepts `secp256k1:`-prefixed base58 keys in the 64-byte uncompressed form returned by the
+/// MPC contract as well as 33-byte compressed and 65-byte SEC1 forms, and raw SEC1 hex with an
+/// optional `0x` prefix.
+///
 /// # Example
 ///
 /// ```
@@ -18,20 +32,107 @@ use crate::types::ScalarExt;
 ///
 /// let verifying_key = naj_pk_to_verifying_key("secp256k1:54hU5wcCmVUPFWLDALXMh1fFToZsVXrx9BbTbHzSfQq1Kd1rJZi52iPa4QQxo6s5TgjWqgpY8HamYuUDzG6fAaUq");
 /// assert!(verifying_key.is_ok());
+///
+/// let verifying_key = naj_pk_to_verifying_key("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+/// assert!(verifying_key.is_ok());
+///
+/// assert!(naj_pk_to_verifying_key("ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp").is_err());
+/// assert!(naj_pk_to_verifying_key("secp256k1:not-base58").is_err());
 /// ```
-pub fn naj_pk_to_verifying_key(root_pk: &str) -> Result<VerifyingKey, Error> {
-    let root_pk = root_pk.split(":").nth(1).expect("Invalid root public key");
-    let root_pk = bs58::decode(root_pk).into_vec().unwrap();
-    let mut sec1_key = vec![0x04];
-    sec1_key.extend_from_slice(&root_pk);
-    VerifyingKey::from_sec1_bytes(&sec1_key)
+pub fn naj_pk_to_verifying_key(root_pk: &str) -> Result<VerifyingKey, KdfError> {
+    let root_pk = root_pk.trim();
+
+    if let Some((key_type, data)) = root_pk.split_once(':') {
+        if key_type != "secp256k1" {
+            return Err(KdfError::UnsupportedKeyType(key_type.to_string()));
+        }
+        let bytes = bs58::decode(data)
+            .into_vec()
+            .map_err(|e| KdfError::InvalidBase58(e.to_string()))?;
+        return sec1_bytes_to_verifying_key(&bytes);
+    }
+
+    let bytes = hex::decode(root_pk.strip_prefix("0x").unwrap_or(root_pk))
+        .map_err(|e| KdfError::InvalidHex(e.to_string()))?;
+    sec1_bytes_to_verifying_key(&bytes)
+}
+
+/// Converts a `near_sdk::PublicKey`, as returned by the signer contract's `public_key` method,
+/// to a VerifyingKey.
+pub fn near_sdk_pk_to_verifying_key(
+    public_key: &near_sdk::PublicKey,
+) -> Result<VerifyingKey, KdfError> {
+    match public_key.curve_type() {
+        CurveType::SECP256K1 => sec1_bytes_to_verifying_key(&public_key.as_bytes()[1..]),
+        CurveType::ED25519 => Err(KdfError::UnsupportedKeyType("ed25519".to_string())),
+    }
+}
+
+/// Converts a `near_crypto::PublicKey` to a VerifyingKey.
+pub fn near_crypto_pk_to_verifying_key(
+    public_key: &near_crypto::PublicKey,
+) -> Result<VerifyingKey, KdfError> {
+    match public_key.key_type() {
+        KeyType::SECP256K1 => sec1_bytes_to_verifying_key(public_key.key_data()),
+        key_type => Err(KdfError::UnsupportedKeyType(key_type.to_string())),
+    }
+}
+
+/// Converts a VerifyingKey to the NAJ form used by the MPC contract.
+///
+/// # Example
+///
+/// ```
+/// use utils::kdf::{naj_pk_to_verifying_key, verifying_key_to_naj_pk};
+///
+/// let naj_public_key = "secp256k1:54hU5wcCmVUPFWLDALXMh1fFToZsVXrx9BbTbHzSfQq1Kd1rJZi52iPa4QQxo6s5TgjWqgpY8HamYuUDzG6fAaUq";
+/// let verifying_key = naj_pk_to_verifying_key(naj_public_key).unwrap();
+/// assert_eq!(verifying_key_to_naj_pk(&verifying_key), naj_public_key);
+/// ```
+pub fn verifying_key_to_naj_pk(public_key: &VerifyingKey) -> String {
+    let encoded_point = public_key.to_encoded_point(false);
+    format!(
+        "secp256k1:{}",
+        bs58::encode(&encoded_point.as_bytes()[1..]).into_string()
+    )
+}
+
+fn sec1_bytes_to_verifying_key(bytes: &[u8]) -> Result<VerifyingKey, KdfError> {
+    match bytes.len() {
+        64 => {
+            let mut sec1_key = [0u8; 65];
+            sec1_key[0] = 0x04;
+            sec1_key[1..].copy_from_slice(bytes);
+            Ok(VerifyingKey::from_sec1_bytes(&sec1_key)?)
+        }
+        33 | 65 => Ok(VerifyingKey::from_sec1_bytes(bytes)?),
+        len => Err(KdfError::InvalidKeyLength(len)),
+    }
 }
 
-pub async fn derive_epsilon(predecessor: String, path: String) -> Scalar {
+/// Derives epsilon using the key version 0 scheme.
+pub fn derive_epsilon(predecessor: String, path: DerivationPath) -> Scalar {
+    derive_epsilon_for_version(KeyVersion::V0, predecessor, path)
+        .expect("key version 0 is always supported")
+}
+
+/// Derives epsilon using the scheme of the given MPC key version.
+pub fn derive_epsilon_for_version(
+    key_version: KeyVersion,
+    predecessor: String,
+    path: DerivationPath,
+) -> Result<Scalar, KdfError> {
+    Ok(epsilon_with_prefix(
+        key_version.epsilon_prefix()?,
+        &predecessor,
+        path.as_str(),
+    ))
+}
+
+fn epsilon_with_prefix(prefix: &str, predecessor: &str, path: &str) -> Scalar {
     let mut hasher = Sha3_256::new();
 
-    let derivation_path =
-        format!("near-mpc-recovery v0.1.0 epsilon derivation:{predecessor},{path}");
+    let derivation_path = format!("{prefix}{predecessor},{path}");
     hasher.update(derivation_path);
     let hash: [u8; 32] = hasher.finalize().into();
 
@@ -49,30 +150,217 @@ pub async fn derive_epsilon(predecessor: String, path: String) -> Scalar {
 /// let child_pk = derive_child_public_key(
 ///     &verifying_key,
 ///     "account_id".to_string(),
-///     "path".to_string(),
+///     "path".parse().unwrap(),
 /// );
 /// assert!(child_pk.is_ok());
 /// ```
-pub async fn derive_child_public_key(
+pub fn derive_child_public_key(
     public_key: &VerifyingKey,
     predecessor: String,
-    path: String,
-) -> Result<VerifyingKey, Error> {
-    let epsilon = derive_epsilon(predecessor, path).await;
+    path: DerivationPath,
+) -> Result<VerifyingKey, KdfError> {
+    derive_child_public_key_for_version(public_key, KeyVersion::V0, predecessor, path)
+}
+
+/// Derives a child public key using the epsilon scheme of the given MPC key version.
+/// `public_key` must be the root key of that same version.
+pub fn derive_child_public_key_for_version(
+    public_key: &VerifyingKey,
+    key_version: KeyVersion,
+    predecessor: String,
+    path: DerivationPath,
+) -> Result<VerifyingKey, KdfError> {
+    let epsilon = derive_epsilon_for_version(key_version, predecessor, path)?;
 
     let new_public_key = (AffinePoint::GENERATOR * epsilon + public_key.as_affine()).to_affine();
-    VerifyingKey::from_affine(new_public_key)
+    Ok(VerifyingKey::from_affine(new_public_key)?)
 }
 
-pub async fn derive_eth_address(
+pub fn derive_eth_address(
     naj_public_key: &str,
     predecessor: String,
-    path: String,
-) -> Result<String, Error> {
+    path: DerivationPath,
+) -> Result<String, KdfError> {
     let root_public_key = naj_pk_to_verifying_key(naj_public_key)?;
-    let child_public_key = derive_child_public_key(&root_public_key, predecessor, path).await?;
+    let child_public_key = derive_child_public_key(&root_public_key, predecessor, path)?;
+
+    Ok(eth_address_from_public_key(&child_public_key))
+}
 
-    let encoded_point = child_public_key.to_encoded_point(false);
+/// Encodes a secp256k1 public key as a `0x`-prefixed Ethereum address.
+pub fn eth_address_from_public_key(public_key: &VerifyingKey) -> String {
+    let encoded_point = public_key.to_encoded_point(false);
     let address = &keccak256(&encoded_point.as_bytes()[1..])[12..];
-    Ok(format!("0x{}", hex::encode(address)))
+    format!("0x{}", hex::encode(address))
+}
+
+/// Root public keys of the MPC network indexed by key version, so that addresses derived under
+/// an older key version keep resolving after a key rotation.
+///
+/// # Example
+///
+/// ```
+/// use utils::kdf::{naj_pk_to_verifying_key, RootPublicKeys};
+/// use utils::types::KeyVersion;
+///
+/// let mut root_public_keys = RootPublicKeys::new();
+/// root_public_keys.insert(
+///     KeyVersion::V0,
+///     naj_pk_to_verifying_key("secp256k1:54hU5wcCmVUPFWLDALXMh1fFToZsVXrx9BbTbHzSfQq1Kd1rJZi52iPa4QQxo6s5TgjWqgpY8HamYuUDzG6fAaUq").unwrap(),
+/// );
+///
+/// let child_pk = root_public_keys.derive_child_public_key(
+///     KeyVersion::V0,
+///     "account_id".to_string(),
+///     "path".parse().unwrap(),
+/// );
+/// assert!(child_pk.is_ok());
+/// assert!(root_public_keys.get(KeyVersion(1)).is_err());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RootPublicKeys {
+    keys: BTreeMap<KeyVersion, VerifyingKey>,
+}
+
+impl RootPublicKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, key_version: KeyVersion, public_key: VerifyingKey) {
+        self.keys.insert(key_version, public_key);
+    }
+
+    pub fn get(&self, key_version: KeyVersion) -> Result<&VerifyingKey, KdfError> {
+        self.keys
+            .get(&key_version)
+            .ok_or(KdfError::MissingRootKey(key_version.0))
+    }
+
+    pub fn derive_child_public_key(
+        &self,
+        key_version: KeyVersion,
+        predecessor: String,
+        path: DerivationPath,
+    ) -> Result<VerifyingKey, KdfError> {
+        derive_child_public_key_for_version(self.get(key_version)?, key_version, predecessor, path)
+    }
+}
+
+/// Derives the Bitcoin address the MPC contract signs for, given the contract's root key,
+/// the signing account and the derivation path.
+pub fn derive_btc_address(
+    naj_public_key: &str,
+    predecessor: String,
+    path: DerivationPath,
+    address_type: BitcoinAddressType,
+    network: BitcoinNetwork,
+) -> Result<String, KdfError> {
+    let root_public_key = naj_pk_to_verifying_key(naj_public_key)?;
+    let child_public_key = derive_child_public_key(&root_public_key, predecessor, path)?;
+
+    btc_address_from_public_key(&child_public_key, address_type, network)
+}
+
+/// Encodes a secp256k1 public key as a Bitcoin address. Legacy and segwit v0 addresses
+/// always commit to the compressed key encoding.
+///
+/// # Example
+///
+/// ```
+/// use k256::ecdsa::VerifyingKey;
+/// use utils::kdf::btc_address_from_public_key;
+/// use utils::types::{BitcoinAddressType, BitcoinNetwork};
+///
+/// let generator = VerifyingKey::from_affine(k256::AffinePoint::GENERATOR).unwrap();
+/// let address = btc_address_from_public_key(
+///     &generator,
+///     BitcoinAddressType::P2WPKH,
+///     BitcoinNetwork::Mainnet,
+/// );
+/// assert_eq!(address.unwrap(), "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4");
+/// ```
+pub fn btc_address_from_public_key(
+    public_key: &VerifyingKey,
+    address_type: BitcoinAddressType,
+    network: BitcoinNetwork,
+) -> Result<String, KdfError> {
+    let compressed = public_key.to_encoded_point(true);
+
+    match address_type {
+        BitcoinAddressType::P2PKH => Ok(base58check(
+            p2pkh_version(network),
+            &hash160(compressed.as_bytes()),
+        )),
+        BitcoinAddressType::P2SHP2WPKH => {
+            let mut redeem_script = vec![0x00, 0x14];
+            redeem_script.extend_from_slice(&hash160(compressed.as_bytes()));
+            Ok(base58check(p2sh_version(network), &hash160(&redeem_script)))
+        }
+        BitcoinAddressType::P2WPKH => {
+            segwit::encode_v0(bech32_hrp(network), &hash160(compressed.as_bytes()))
+                .map_err(|e| KdfError::AddressEncoding(e.to_string()))
+        }
+        BitcoinAddressType::P2TR => {
+            let output_key = taproot_output_key(public_key)?;
+            segwit::encode_v1(bech32_hrp(network), &output_key)
+                .map_err(|e| KdfError::AddressEncoding(e.to_string()))
+        }
+    }
+}
+
+fn p2pkh_version(network: BitcoinNetwork) -> u8 {
+    match network {
+        BitcoinNetwork::Mainnet => 0x00,
+        BitcoinNetwork::Testnet | BitcoinNetwork::Signet | BitcoinNetwork::Regtest => 0x6f,
+    }
+}
+
+fn p2sh_version(network: BitcoinNetwork) -> u8 {
+    match network {
+        BitcoinNetwork::Mainnet => 0x05,
+        BitcoinNetwork::Testnet | BitcoinNetwork::Signet | BitcoinNetwork::Regtest => 0xc4,
+    }
+}
+
+fn bech32_hrp(network: BitcoinNetwork) -> Hrp {
+    match network {
+        BitcoinNetwork::Mainnet => hrp::BC,
+        BitcoinNetwork::Testnet | BitcoinNetwork::Signet => hrp::TB,
+        BitcoinNetwork::Regtest => hrp::BCRT,
+    }
+}
+
+fn hash160(data: &[u8]) -> [u8; 20] {
+    Ripemd160::digest(Sha256::digest(data)).into()
+}
+
+fn base58check(version: u8, payload: &[u8]) -> String {
+    let mut data = vec![version];
+    data.extend_from_slice(payload);
+    let checksum = Sha256::digest(Sha256::digest(&data));
+    data.extend_from_slice(&checksum[..4]);
+    bs58::encode(data).into_string()
+}
+
+/// Computes the BIP-86 tweaked x-only output key for a key-path only Taproot output.
+fn taproot_output_key(public_key: &VerifyingKey) -> Result<[u8; 32], KdfError> {
+    let mut internal_key = ProjectivePoint::from(*public_key.as_affine());
+    if bool::from(internal_key.to_affine().y_is_odd()) {
+        internal_key = -internal_key;
+    }
+    let x_only: [u8; 32] = internal_key.to_affine().x().into();
+
+    let tag = Sha256::digest(b"TapTweak");
+    let tweak: [u8; 32] = Sha256::new()
+        .chain_update(tag)
+        .chain_update(tag)
+        .chain_update(x_only)
+        .finalize()
+        .into();
+    let tweak = Option::<Scalar>::from(Scalar::from_repr(tweak.into()))
+        .ok_or_else(|| KdfError::AddressEncoding("taproot tweak out of range".to_string()))?;
+
+    let output_key = (internal_key + ProjectivePoint::GENERATOR * tweak).to_affine();
+    Ok(output_key.x().into())
 }
@@ -1,2 +1,6 @@
+pub mod error;
 pub mod kdf;
+pub mod signature;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod signer;
 pub mod types;
@@ -0,0 +1,193 @@
+use ethers_core::types::U256;
+use k256::{
+    ecdsa::{signature::hazmat::PrehashVerifier, RecoveryId, Signature, VerifyingKey},
+    elliptic_curve::{
+        ops::Reduce,
+        point::{AffineCoordinates, DecompressPoint},
+        scalar::IsHigh,
+        subtle::Choice,
+        PrimeField,
+    },
+    AffinePoint, Scalar,
+};
+
+use crate::error::SignatureError;
+use crate::types::{SerializableAffinePoint, SerializableScalar, SignatureResponse};
+
+/// Verification, recovery and format conversions for signatures returned by the MPC contract.
+///
+/// # Example
+///
+/// ```
+/// use k256::ecdsa::SigningKey;
+/// use utils::types::SignatureResponse;
+///
+/// let signing_key = SigningKey::from_slice(&[1; 32]).unwrap();
+/// let payload = [7; 32];
+/// let (signature, recovery_id) = signing_key.sign_prehash_recoverable(&payload).unwrap();
+///
+/// let response = SignatureResponse::from_recoverable(&signature, recovery_id).unwrap();
+/// assert!(response.verify(signing_key.verifying_key(), &payload).is_ok());
+/// assert_eq!(response.recover(&payload).unwrap(), *signing_key.verifying_key());
+/// assert!(response.verify(signing_key.verifying_key(), &[8; 32]).is_err());
+///
+/// // The malleable high-S twin recovers the same key but only verifies once normalized.
+/// let mut high_s = response.clone();
+/// high_s.s.scalar = -high_s.s.scalar;
+/// high_s.big_r.affine_point = -high_s.big_r.affine_point;
+/// high_s.recovery_id ^= 1;
+/// assert!(high_s.verify(signing_key.verifying_key(), &payload).is_err());
+/// assert_eq!(high_s.recover(&payload).unwrap(), *signing_key.verifying_key());
+/// assert_eq!(high_s.normalize_s(), response);
+///
+/// assert_eq!(response.to_compact().unwrap()[..], signature.to_bytes()[..]);
+/// assert_eq!(response.to_compact_recoverable().unwrap()[64], recovery_id.to_byte());
+/// assert_eq!(response.to_der().unwrap(), signature.to_der().as_bytes());
+/// assert_eq!(response.to_ethers_signature(Some(1)).unwrap().v, 37 + recovery_id.to_byte() as u64);
+/// ```
+impl SignatureResponse {
+    /// Builds a response from a k256 signature, reconstructing `big_r` from `r` and the y parity
+    /// of the recovery id.
+    pub fn from_recoverable(
+        signature: &Signature,
+        recovery_id: RecoveryId,
+    ) -> Result<Self, SignatureError> {
+        if recovery_id.is_x_reduced() {
+            return Err(SignatureError::Malformed(k256::ecdsa::Error::new()));
+        }
+
+        let big_r = Option::<AffinePoint>::from(AffinePoint::decompress(
+            &signature.r().to_bytes(),
+            Choice::from(recovery_id.is_y_odd() as u8),
+        ))
+        .ok_or_else(|| SignatureError::Malformed(k256::ecdsa::Error::new()))?;
+
+        Ok(Self {
+            big_r: SerializableAffinePoint {
+                affine_point: big_r,
+            },
+            s: SerializableScalar {
+                scalar: *signature.s().as_ref(),
+            },
+            recovery_id: recovery_id.to_byte(),
+        })
+    }
+
+    /// `r`, the x-coordinate of `big_r` reduced modulo the curve order.
+    pub fn r(&self) -> Scalar {
+        <Scalar as Reduce<k256::U256>>::reduce_bytes(&self.big_r.affine_point.x())
+    }
+
+    /// The recovery id implied by `big_r`. Fails if it differs from the reported `recovery_id`.
+    pub fn recovery_id(&self) -> Result<RecoveryId, SignatureError> {
+        let x_reduced = bool::from(Scalar::from_repr(self.big_r.affine_point.x()).is_none());
+        let expected = RecoveryId::new(self.big_r.affine_point.y_is_odd().into(), x_reduced);
+
+        if expected.to_byte() != self.recovery_id {
+            return Err(SignatureError::RecoveryIdMismatch {
+                expected: expected.to_byte(),
+                actual: self.recovery_id,
+            });
+        }
+
+        Ok(expected)
+    }
+
+    /// Whether `s` is in the upper half of the curve order. Such signatures are malleable and
+    /// rejected by Bitcoin (BIP-62) and Ethereum (EIP-2).
+    pub fn is_high_s(&self) -> bool {
+        self.s.scalar.is_high().into()
+    }
+
+    /// Returns the equivalent low-S signature. Negating `s` corresponds to negating `big_r`,
+    /// which flips the y parity of the recovery id.
+    pub fn normalize_s(&self) -> Self {
+        if !self.is_high_s() {
+            return self.clone();
+        }
+
+        Self {
+            big_r: SerializableAffinePoint {
+                affine_point: -self.big_r.affine_point,
+            },
+            s: SerializableScalar {
+                scalar: -self.s.scalar,
+            },
+            recovery_id: self.recovery_id ^ 1,
+        }
+    }
+
+    /// Recovers the public key that signed `payload`, for either S form.
+    pub fn recover(&self, payload: &[u8; 32]) -> Result<VerifyingKey, SignatureError> {
+        let normalized = self.normalize_s();
+        let recovery_id = normalized.recovery_id()?;
+
+        VerifyingKey::recover_from_prehash(payload, &normalized.to_k256_signature()?, recovery_id)
+            .map_err(|_| SignatureError::VerificationFailed)
+    }
+
+    /// Checks that this is a low-S signature of `payload` by `public_key` whose recovery id
+    /// recovers `public_key`, as chains that recover the sender from the signature require.
+    pub fn verify(
+        &self,
+        public_key: &VerifyingKey,
+        payload: &[u8; 32],
+    ) -> Result<(), SignatureError> {
+        if self.is_high_s() {
+            return Err(SignatureError::HighS);
+        }
+
+        public_key
+            .verify_prehash(payload, &self.to_k256_signature()?)
+            .map_err(|_| SignatureError::VerificationFailed)?;
+
+        if self.recover(payload)? != *public_key {
+            return Err(SignatureError::VerificationFailed);
+        }
+
+        Ok(())
+    }
+
+    pub fn to_k256_signature(&self) -> Result<Signature, SignatureError> {
+        Ok(Signature::from_scalars(
+            self.r().to_bytes(),
+            self.s.scalar.to_bytes(),
+        )?)
+    }
+
+    /// The 64-byte `r || s` encoding.
+    pub fn to_compact(&self) -> Result<[u8; 64], SignatureError> {
+        Ok(self.to_k256_signature()?.to_bytes().into())
+    }
+
+    /// The 65-byte `r || s || v` encoding, with `v` the raw recovery id.
+    pub fn to_compact_recoverable(&self) -> Result<[u8; 65], SignatureError> {
+        let mut bytes = [0u8; 65];
+        bytes[..64].copy_from_slice(&self.to_compact()?);
+        bytes[64] = self.recovery_id()?.to_byte();
+        Ok(bytes)
+    }
+
+    /// The ASN.1 DER encoding.
+    pub fn to_der(&self) -> Result<Vec<u8>, SignatureError> {
+        Ok(self.to_k256_signature()?.to_der().as_bytes().to_vec())
+    }
+
+    /// Converts to an ethers signature. With a `chain_id`, `v` is EIP-155 encoded, which ethers
+    /// accepts for every transaction type; without one, `v` is 27 or 28.
+    pub fn to_ethers_signature(
+        &self,
+        chain_id: Option<u64>,
+    ) -> Result<ethers_core::types::Signature, SignatureError> {
+        let y_parity = self.recovery_id()?.is_y_odd() as u64;
+
+        Ok(ethers_core::types::Signature {
+            r: U256::from_big_endian(&self.r().to_bytes()),
+            s: U256::from_big_endian(&self.s.scalar.to_bytes()),
+            v: match chain_id {
+                Some(chain_id) => y_parity + 35 + chain_id * 2,
+                None => y_parity + 27,
+            },
+        })
+    }
+}
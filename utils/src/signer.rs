@@ -0,0 +1,258 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use near_crypto::{InMemorySigner, KeyFile, PublicKey, SecretKey, Signature, Signer};
+use near_primitives::transaction::{SignedTransaction, Transaction};
+use near_sdk::{serde_json::json, AccountId};
+use serde::Deserialize;
+
+use crate::error::SignerError;
+
+/// Signs NEAR transactions on behalf of a single access key.
+///
+/// The chain signature clients only need the account and public key to build transactions,
+/// so implementations are free to keep the secret key out of the process entirely.
+#[async_trait]
+pub trait NearSigner: Send + Sync {
+    fn account_id(&self) -> &AccountId;
+
+    fn public_key(&self) -> &PublicKey;
+
+    async fn sign_transaction(
+        &self,
+        transaction: Transaction,
+    ) -> Result<SignedTransaction, SignerError>;
+}
+
+#[async_trait]
+impl NearSigner for InMemorySigner {
+    fn account_id(&self) -> &AccountId {
+        &self.account_id
+    }
+
+    fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    async fn sign_transaction(
+        &self,
+        transaction: Transaction,
+    ) -> Result<SignedTransaction, SignerError> {
+        let signature = self.sign(transaction.get_hash_and_size().0.as_ref());
+        Ok(SignedTransaction::new(signature, transaction))
+    }
+}
+
+/// Signs with a key file in the near-cli format, such as those in `~/.near-credentials`.
+///
+/// The file is read again for every transaction, so the secret key is only held in memory
+/// while signing.
+pub struct KeystoreSigner {
+    path: PathBuf,
+    account_id: AccountId,
+    public_key: PublicKey,
+}
+
+impl KeystoreSigner {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, SignerError> {
+        let path = path.as_ref().to_path_buf();
+        let key_file = read_key_file(&path)?;
+
+        Ok(Self {
+            path,
+            account_id: key_file.account_id,
+            public_key: key_file.public_key,
+        })
+    }
+
+    /// Loads `~/.near-credentials/<network_id>/<account_id>.json`, where `network_id` is the
+    /// near-cli network name such as `mainnet` or `testnet`.
+    pub fn from_near_credentials(
+        network_id: &str,
+        account_id: &AccountId,
+    ) -> Result<Self, SignerError> {
+        let home = std::env::var_os("HOME")
+            .or_else(|| std::env::var_os("USERPROFILE"))
+            .ok_or_else(|| SignerError::KeyFile("home directory not found".to_string()))?;
+
+        Self::from_file(
+            PathBuf::from(home)
+                .join(".near-credentials")
+                .join(network_id)
+                .join(format!("{}.json", account_id)),
+        )
+    }
+}
+
+#[async_trait]
+impl NearSigner for KeystoreSigner {
+    fn account_id(&self) -> &AccountId {
+        &self.account_id
+    }
+
+    fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    async fn sign_transaction(
+        &self,
+        transaction: Transaction,
+    ) -> Result<SignedTransaction, SignerError> {
+        let key_file = read_key_file(&self.path)?;
+        if key_file.public_key != self.public_key {
+            return Err(SignerError::KeyMismatch(self.public_key.to_string()));
+        }
+
+        InMemorySigner::from_secret_key(key_file.account_id, key_file.secret_key)
+            .sign_transaction(transaction)
+            .await
+    }
+}
+
+fn read_key_file(path: &Path) -> Result<KeyFile, SignerError> {
+    let key_file = KeyFile::from_file(path)
+        .map_err(|e| SignerError::KeyFile(format!("{}: {}", path.display(), e)))?;
+
+    if key_file.secret_key.public_key() != key_file.public_key {
+        return Err(SignerError::KeyMismatch(key_file.public_key.to_string()));
+    }
+
+    Ok(key_file)
+}
+
+/// Signs with a secret key read from an environment variable for every transaction.
+pub struct EnvSigner {
+    secret_key_var: String,
+    account_id: AccountId,
+    public_key: PublicKey,
+}
+
+impl EnvSigner {
+    /// Reads the account from `account_id_var` and the secret key from `secret_key_var`.
+    pub fn new(account_id_var: &str, secret_key_var: &str) -> Result<Self, SignerError> {
+        let account_id = env_var(account_id_var)?
+            .parse()
+            .map_err(|e| SignerError::InvalidKey(format!("{}: {}", account_id_var, e)))?;
+
+        Ok(Self {
+            secret_key_var: secret_key_var.to_string(),
+            account_id,
+            public_key: env_secret_key(secret_key_var)?.public_key(),
+        })
+    }
+
+    /// Uses `NEAR_ACCOUNT_ID` and `NEAR_PRIVATE_KEY`.
+    pub fn from_env() -> Result<Self, SignerError> {
+        Self::new("NEAR_ACCOUNT_ID", "NEAR_PRIVATE_KEY")
+    }
+}
+
+#[async_trait]
+impl NearSigner for EnvSigner {
+    fn account_id(&self) -> &AccountId {
+        &self.account_id
+    }
+
+    fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    async fn sign_transaction(
+        &self,
+        transaction: Transaction,
+    ) -> Result<SignedTransaction, SignerError> {
+        let secret_key = env_secret_key(&self.secret_key_var)?;
+        if secret_key.public_key() != self.public_key {
+            return Err(SignerError::KeyMismatch(self.public_key.to_string()));
+        }
+
+        InMemorySigner::from_secret_key(self.account_id.clone(), secret_key)
+            .sign_transaction(transaction)
+            .await
+    }
+}
+
+fn env_var(name: &str) -> Result<String, SignerError> {
+    std::env::var(name).map_err(|_| SignerError::MissingEnvVar(name.to_string()))
+}
+
+fn env_secret_key(name: &str) -> Result<SecretKey, SignerError> {
+    env_var(name)?
+        .parse()
+        .map_err(|e| SignerError::InvalidKey(format!("{}: {}", name, e)))
+}
+
+/// Stand-in for a remote signing service such as an HSM, KMS or custody API, so the secret key
+/// never enters this process.
+///
+/// The transaction hash is POSTed to `url` as
+/// `{"account_id": ..., "public_key": "ed25519:...", "hash": "<base58>"}` and the service answers
+/// `{"signature": "ed25519:..."}`. The signature is checked against `public_key` before use.
+pub struct RemoteSigner {
+    url: String,
+    http_client: reqwest::Client,
+    account_id: AccountId,
+    public_key: PublicKey,
+}
+
+#[derive(Deserialize)]
+struct RemoteSignature {
+    signature: Signature,
+}
+
+impl RemoteSigner {
+    pub fn new(url: &str, account_id: AccountId, public_key: PublicKey) -> Self {
+        Self {
+            url: url.to_string(),
+            http_client: reqwest::Client::new(),
+            account_id,
+            public_key,
+        }
+    }
+
+    /// Uses a preconfigured HTTP client, e.g. one with authentication headers or mTLS.
+    pub fn with_http_client(mut self, http_client: reqwest::Client) -> Self {
+        self.http_client = http_client;
+        self
+    }
+}
+
+#[async_trait]
+impl NearSigner for RemoteSigner {
+    fn account_id(&self) -> &AccountId {
+        &self.account_id
+    }
+
+    fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    async fn sign_transaction(
+        &self,
+        transaction: Transaction,
+    ) -> Result<SignedTransaction, SignerError> {
+        let (hash, _) = transaction.get_hash_and_size();
+
+        let response: RemoteSignature = self
+            .http_client
+            .post(&self.url)
+            .json(&json!({
+                "account_id": self.account_id,
+                "public_key": self.public_key,
+                "hash": hash,
+            }))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|e| SignerError::Remote(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| SignerError::Remote(e.to_string()))?;
+
+        if !response.signature.verify(hash.as_ref(), &self.public_key) {
+            return Err(SignerError::KeyMismatch(self.public_key.to_string()));
+        }
+
+        Ok(SignedTransaction::new(response.signature, transaction))
+    }
+}
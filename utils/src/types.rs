@@ -1,17 +1,192 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Arc;
+
 use ethers_core::k256::{elliptic_curve::scalar::FromUintUnchecked, AffinePoint, Scalar, U256};
 
-use near_crypto::InMemorySigner;
 use near_sdk::AccountId;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+use crate::error::KdfError;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::signer::NearSigner;
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct SignRequest {
     pub payload: [u8; 32],
-    pub path: String,
+    pub path: DerivationPath,
     pub key_version: u32,
 }
 
+/// Version of the MPC network's root key. Each version has its own root key and its own
+/// epsilon derivation scheme, so the same account and path derive different keys per version.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    JsonSchema,
+)]
+#[serde(transparent)]
+pub struct KeyVersion(pub u32);
+
+impl KeyVersion {
+    pub const V0: KeyVersion = KeyVersion(0);
+
+    /// The prefix hashed together with the predecessor and path to produce epsilon.
+    pub fn epsilon_prefix(&self) -> Result<&'static str, KdfError> {
+        match self.0 {
+            0 => Ok("near-mpc-recovery v0.1.0 epsilon derivation:"),
+            version => Err(KdfError::UnsupportedKeyVersion(version)),
+        }
+    }
+}
+
+impl From<KeyVersion> for u32 {
+    fn from(version: KeyVersion) -> Self {
+        version.0
+    }
+}
+
+impl fmt::Display for KeyVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+/// A validated key derivation path.
+///
+/// The path is kept byte for byte, since epsilon hashes its exact bytes and any rewrite would
+/// derive a different key than the one the MPC contract signs with. Parsing only rejects paths
+/// that are empty, contain control characters or have leading or trailing whitespace.
+///
+/// [`DerivationPath::chain`] builds the structured `chain,index` form, e.g. `ethereum,1`.
+///
+/// # Example
+///
+/// ```
+/// use utils::types::DerivationPath;
+///
+/// let path: DerivationPath = "ethereum,1".parse().unwrap();
+/// assert_eq!(path, DerivationPath::chain("ethereum", 1).unwrap());
+/// assert_eq!(path.chain_name(), Some("ethereum"));
+/// assert_eq!(path.index(), Some(1));
+///
+/// let path: DerivationPath = "eth/0".parse().unwrap();
+/// assert_eq!(path.as_str(), "eth/0");
+/// assert_eq!(path.chain_name(), None);
+///
+/// assert!("".parse::<DerivationPath>().is_err());
+/// assert!(" eth".parse::<DerivationPath>().is_err());
+/// assert!(DerivationPath::chain("Ethereum", 1).is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(try_from = "String", into = "String")]
+pub struct DerivationPath(String);
+
+impl DerivationPath {
+    /// Builds a structured `chain,index` path. `chain` must be lowercase ASCII letters, digits,
+    /// `-` or `_`.
+    pub fn chain(chain: &str, index: u32) -> Result<Self, KdfError> {
+        if chain.is_empty() || !chain.chars().all(is_chain_char) {
+            return Err(KdfError::InvalidDerivationPath(format!(
+                "invalid chain name {:?}",
+                chain
+            )));
+        }
+
+        Ok(Self(format!("{},{}", chain, index)))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The chain component of a structured path.
+    pub fn chain_name(&self) -> Option<&str> {
+        self.structured().map(|(chain, _)| chain)
+    }
+
+    /// The index component of a structured path.
+    pub fn index(&self) -> Option<u32> {
+        self.structured().map(|(_, index)| index)
+    }
+
+    /// Splits a path of the exact form [`DerivationPath::chain`] builds.
+    fn structured(&self) -> Option<(&str, u32)> {
+        let (chain, index) = self.0.split_once(',')?;
+        let parsed: u32 = index.parse().ok()?;
+
+        (!chain.is_empty() && chain.chars().all(is_chain_char) && parsed.to_string() == index)
+            .then_some((chain, parsed))
+    }
+}
+
+fn is_chain_char(c: char) -> bool {
+    c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_'
+}
+
+impl FromStr for DerivationPath {
+    type Err = KdfError;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        if path.is_empty() {
+            return Err(KdfError::InvalidDerivationPath(
+                "path must not be empty".to_string(),
+            ));
+        }
+        if path.chars().any(char::is_control) {
+            return Err(KdfError::InvalidDerivationPath(
+                "path must not contain control characters".to_string(),
+            ));
+        }
+        if path.trim() != path {
+            return Err(KdfError::InvalidDerivationPath(format!(
+                "path {:?} has leading or trailing whitespace",
+                path
+            )));
+        }
+
+        Ok(Self(path.to_string()))
+    }
+}
+
+impl TryFrom<String> for DerivationPath {
+    type Error = KdfError;
+
+    fn try_from(path: String) -> Result<Self, Self::Error> {
+        path.parse()
+    }
+}
+
+impl From<DerivationPath> for String {
+    fn from(path: DerivationPath) -> Self {
+        path.0
+    }
+}
+
+impl AsRef<str> for DerivationPath {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
 pub trait ScalarExt {
     fn from_bytes(bytes: &[u8]) -> Self;
 }
@@ -41,17 +216,77 @@ pub struct SignatureResponse {
     pub recovery_id: u8,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
 pub enum NearNetwork {
     Mainnet,
     Testnet,
+    /// A node on `http://localhost:3030`, the default RPC address of localnet and sandbox nodes.
+    Localnet,
+    /// A self-hosted or third-party RPC node. Requests fail over to `fallback_urls`, in order,
+    /// when `rpc_url` is unavailable.
+    Custom {
+        rpc_url: String,
+        fallback_urls: Vec<String>,
+        /// Extra HTTP headers sent with every request, e.g. for an authenticating proxy.
+        headers: HashMap<String, String>,
+        /// Sent as the `x-api-key` header.
+        api_key: Option<String>,
+    },
+}
+
+impl NearNetwork {
+    /// A custom endpoint without fallbacks, headers or API key.
+    pub fn custom(rpc_url: impl Into<String>) -> Self {
+        NearNetwork::Custom {
+            rpc_url: rpc_url.into(),
+            fallback_urls: vec![],
+            headers: HashMap::new(),
+            api_key: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum BitcoinNetwork {
+    Mainnet,
+    Testnet,
+    Signet,
+    Regtest,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum BitcoinAddressType {
+    /// Legacy pay-to-pubkey-hash, base58check encoded.
+    P2PKH,
+    /// Pay-to-witness-pubkey-hash nested in pay-to-script-hash, base58check encoded.
+    P2SHP2WPKH,
+    /// Native segwit v0 pay-to-witness-pubkey-hash, bech32 encoded.
+    P2WPKH,
+    /// Taproot key-path (BIP-86, no script tree), bech32m encoded.
+    P2TR,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
 #[derive(Clone)]
 pub struct NearAuthentication {
     pub network: NearNetwork,
-    pub account_id: AccountId,
-    pub key_pair: InMemorySigner,
+    pub signer: Arc<dyn NearSigner>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl NearAuthentication {
+    /// Authenticates as the account of `signer`.
+    pub fn new(network: NearNetwork, signer: impl NearSigner + 'static) -> Self {
+        Self {
+            network,
+            signer: Arc::new(signer),
+        }
+    }
+
+    /// The account of the signer.
+    pub fn account_id(&self) -> &AccountId {
+        self.signer.account_id()
+    }
 }
 
 pub struct EVMTransaction {